@@ -8,7 +8,7 @@ use switcheroo::stack::*;
 
 thread_local!(
     /// Mock TLS
-    pub static TLS: Cell<*const usize> = Cell::new(ptr::null())
+    pub static TLS: Cell<*const usize> = const { Cell::new(ptr::null()) }
 );
 
 fn async_bench(c: &mut Criterion) {
@@ -33,7 +33,7 @@ fn async_bench(c: &mut Criterion) {
                 async_
             },
             |mut task| {
-                futures::executor::block_on(&mut task);
+                futures::executor::block_on(&mut task).unwrap();
                 task
             },
             BatchSize::SmallInput,
@@ -54,7 +54,7 @@ fn async_bench(c: &mut Criterion) {
                 async_
             },
             |mut task| {
-                futures::executor::block_on(&mut task);
+                futures::executor::block_on(&mut task).unwrap();
                 task
             },
             BatchSize::SmallInput,
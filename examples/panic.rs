@@ -11,5 +11,5 @@ fn main() {
     })
     .unwrap();
 
-    futures::executor::block_on(task);
+    futures::executor::block_on(task).unwrap();
 }
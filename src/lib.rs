@@ -22,8 +22,8 @@
 //! // non-async function
 //! #[allow(improper_ctypes_definitions)]
 //! extern "C" fn non_async(mut yielder: AsyncYielder<u32>) -> u32 {
-//! 	// Suspend the runtime until async value is ready.
-//! 	// Can contain .await calls.
+//!     // Suspend the runtime until async value is ready.
+//!     // Can contain .await calls.
 //!     yielder.async_suspend(async { 42 })
 //! }
 //!
@@ -37,20 +37,24 @@
 //!     .unwrap();
 //!
 //!     let outside = futures::executor::block_on(task);
-//!     assert_eq!(outside, 64);
+//!     assert_eq!(outside.unwrap(), 64);
 //! }
 //! ```
 
-use switcheroo::Generator;
-use switcheroo::Yielder;
+use switcheroo::BorrowedInput;
+use switcheroo::BorrowingGenerator;
+use switcheroo::BorrowingYielder;
+use switcheroo::GeneratorState;
 
 use std::cell::Cell;
 use std::future::Future;
 use std::io::Error;
+use std::marker::PhantomData;
 use std::pin::Pin;
-use std::task::{Context, Poll, Waker};
+use std::task::{Context, Poll};
 
 pub use switcheroo::stack;
+pub use switcheroo::Fault;
 
 /// AsyncWormhole represents a Future that uses a generator with a separate stack to execute a closure.
 ///
@@ -58,6 +62,9 @@ pub use switcheroo::stack;
 /// [AsyncYielder](struct.AsyncYielder). Once all Futures have been awaited on AsyncWormhole will resolve
 /// to the return value of the provided closure.
 ///
+/// If the closure faults (e.g. overflows its stack) AsyncWormhole resolves to the
+/// [Fault](struct.Fault.html) instead. The executor's thread keeps running.
+///
 /// For dealing with thread local storage
 /// [AsyncWormhole::set_pre_post_poll](struct.AsyncWormhole.html#method.set_pre_post_poll) is provided.
 ///
@@ -70,7 +77,7 @@ where
     Stack: stack::Stack + Send,
     P: FnMut() + Send,
 {
-    generator: Option<Cell<Generator<'a, Waker, Option<Output>, Stack>>>,
+    generator: Option<Cell<BorrowingGenerator<'a, ContextInput, (), Output, Stack>>>,
     pre_post_poll: Option<P>,
 }
 
@@ -82,15 +89,34 @@ where
     /// Returns a new AsyncWormhole, using the passed `stack` to execute the closure `f` on.
     /// The closure will not be executed right away, only if you pass AsyncWormhole to an
     /// async executor (.await on it)
+    ///
+    /// Returns an error if the generator can't use the stack (see
+    /// [Generator::new](../switcheroo/struct.Generator.html#method.new)).
     pub fn new<F>(stack: Stack, f: F) -> Result<Self, Error>
     where
         F: FnOnce(AsyncYielder<Output>) -> Output + 'a + Send,
     {
-        let generator = Generator::new(stack, |yielder, waker| {
-            let async_yielder = AsyncYielder::new(yielder, waker);
-            let finished = Some(f(async_yielder));
-            yielder.suspend(finished);
-        });
+        let generator = BorrowingGenerator::new(stack, |yielder| f(AsyncYielder::new(yielder)))?;
+
+        Ok(Self {
+            generator: Some(Cell::new(generator)),
+            pre_post_poll: None,
+        })
+    }
+
+    /// Returns a new AsyncWormhole that measures how much of the stack the closure uses, see
+    /// [stack_high_water_mark](struct.AsyncWormhole.html#method.stack_high_water_mark).
+    pub fn with_measurement<F>(
+        stack: Stack,
+        measurement: stack::Measurement,
+        f: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnOnce(AsyncYielder<Output>) -> Output + 'a + Send,
+    {
+        let generator = BorrowingGenerator::with_measurement(stack, measurement, |yielder| {
+            f(AsyncYielder::new(yielder))
+        })?;
 
         Ok(Self {
             generator: Some(Cell::new(generator)),
@@ -104,6 +130,38 @@ where
         self.pre_post_poll = Some(f);
     }
 
+    /// Sets how many bytes above the top of the stack the stack limit is, see
+    /// [Generator::set_stack_limit_slack](../switcheroo/struct.Generator.html#method.set_stack_limit_slack).
+    pub fn set_stack_limit_slack(&mut self, slack: usize) {
+        self.generator
+            .as_mut()
+            .unwrap()
+            .get_mut()
+            .set_stack_limit_slack(slack)
+    }
+
+    /// Returns the number of bytes of the stack used so far, or `None` if the AsyncWormhole was not
+    /// created [with a measurement](struct.AsyncWormhole.html#method.with_measurement).
+    ///
+    /// See [Generator::stack_high_water_mark](../switcheroo/struct.Generator.html#method.stack_high_water_mark).
+    pub fn stack_high_water_mark(&mut self) -> Option<usize> {
+        self.generator
+            .as_mut()
+            .unwrap()
+            .get_mut()
+            .stack_high_water_mark()
+    }
+
+    /// Returns the number of bytes of the stack that are currently backed by physical memory.
+    pub fn stack_bytes_resident(&mut self) -> Result<usize, Error> {
+        self.generator
+            .as_mut()
+            .unwrap()
+            .get_mut()
+            .stack_bytes_resident()
+            .map_err(Error::from)
+    }
+
     /// Get the stack from the internal generator.
     pub fn stack(mut self) -> Stack {
         let generator = self.generator.take().unwrap().into_inner();
@@ -123,39 +181,30 @@ where
     Stack: stack::Stack + Unpin + Send,
     P: FnMut() + Unpin + Send,
 {
-    type Output = Output;
+    type Output = Result<Output, Fault>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // If we call the future after it completed it will always return Poll::Pending.
+        // But polling a completed future is either way undefined behaviour.
+        if self.generator.as_mut().unwrap().get_mut().finished() {
+            return Poll::Pending;
+        }
+
         // If pre_post_poll is provided execute it before entering separate stack
         if let Some(pre_post_poll) = &mut self.pre_post_poll {
             pre_post_poll()
         }
 
-        match self
-            .generator
-            .as_mut()
-            .unwrap()
-            .get_mut()
-            .resume(cx.waker().clone())
-        {
-            // If we call the future after it completed it will always return Poll::Pending.
-            // But polling a completed future is either way undefined behaviour.
-            None | Some(None) => {
+        match self.generator.as_mut().unwrap().get_mut().resume(cx) {
+            Ok(GeneratorState::Yielded(())) => {
                 // If pre_post_poll is provided execute it before returning a Poll::Pending
                 if let Some(pre_post_poll) = &mut self.pre_post_poll {
                     pre_post_poll()
                 }
                 Poll::Pending
             }
-            Some(Some(out)) => {
-                // Poll one last time to finish the generator
-                self.generator
-                    .as_mut()
-                    .unwrap()
-                    .get_mut()
-                    .resume(cx.waker().clone());
-                Poll::Ready(out)
-            }
+            Ok(GeneratorState::Complete(out)) => Poll::Ready(Ok(out)),
+            Err(fault) => Poll::Ready(Err(fault)),
         }
     }
 }
@@ -179,15 +228,25 @@ where
     }
 }
 
+// The generator is resumed with the `Context` passed to `poll`.
+struct ContextInput;
+
+impl BorrowedInput for ContextInput {
+    type Input<'r> = Context<'r>;
+}
+
 #[derive(Clone)]
 pub struct AsyncYielder<'a, Output> {
-    yielder: &'a Yielder<Waker, Option<Output>>,
-    waker: Waker,
+    yielder: &'a BorrowingYielder<'a, ContextInput, ()>,
+    phantom: PhantomData<fn() -> Output>,
 }
 
 impl<'a, Output> AsyncYielder<'a, Output> {
-    pub(crate) fn new(yielder: &'a Yielder<Waker, Option<Output>>, waker: Waker) -> Self {
-        Self { yielder, waker }
+    pub(crate) fn new(yielder: &'a BorrowingYielder<'a, ContextInput, ()>) -> Self {
+        Self {
+            yielder,
+            phantom: PhantomData,
+        }
     }
 
     /// Takes an `impl Future` and awaits it, returning the value from it once ready.
@@ -197,11 +256,10 @@ impl<'a, Output> AsyncYielder<'a, Output> {
     {
         let mut future = unsafe { Pin::new_unchecked(&mut future) };
         loop {
-            let mut cx = Context::from_waker(&mut self.waker);
-            self.waker = match future.as_mut().poll(&mut cx) {
-                Poll::Pending => self.yielder.suspend(None),
+            match self.yielder.with_input(|cx| future.as_mut().poll(cx)) {
+                Poll::Pending => self.yielder.suspend(()),
                 Poll::Ready(result) => return result,
-            };
+            }
         }
     }
 }
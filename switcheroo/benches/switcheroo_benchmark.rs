@@ -5,13 +5,14 @@ use switcheroo::Generator;
 
 fn switcheroo(c: &mut Criterion) {
     // Bench allocation
-    c.bench_function("create 8 MB stack", |b| b.iter(|| EightMbStack::new()));
+    c.bench_function("create 8 MB stack", |b| b.iter(EightMbStack::new));
 
     c.bench_function("switch stacks", |b| {
         let stack = EightMbStack::new().unwrap();
-        let mut gen = Generator::new(stack, |yielder, input| {
-            black_box(yielder.suspend(input + 1));
-        });
+        let mut gen = Generator::new(stack, |yielder, mut input| loop {
+            input = black_box(yielder.suspend(input + 1));
+        })
+        .unwrap();
         b.iter(|| black_box(gen.resume(2)))
     });
 }
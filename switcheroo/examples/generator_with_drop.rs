@@ -1,5 +1,5 @@
-use switcheroo::Generator;
 use switcheroo::{stack::*, Yielder};
+use switcheroo::{Generator, GeneratorState};
 
 struct DropMarker {}
 
@@ -17,11 +17,12 @@ fn main() {
         input = yielder.suspend(input + 1);
         input = yielder.suspend(input + 1);
         yielder.suspend(input + 1);
-    });
+    })
+    .unwrap();
 
-    assert_eq!(add_one.resume(2), Some(3));
-    assert_eq!(add_one.resume(2), Some(3));
-    assert_eq!(add_one.resume(127), Some(128));
-    // assert_eq!(add_one.resume(0), Some(1));
-    assert_eq!(add_one.finished(), false);
+    assert_eq!(add_one.resume(2), Ok(GeneratorState::Yielded(3)));
+    assert_eq!(add_one.resume(2), Ok(GeneratorState::Yielded(3)));
+    assert_eq!(add_one.resume(127), Ok(GeneratorState::Yielded(128)));
+    // assert_eq!(add_one.resume(0), Ok(GeneratorState::Yielded(1)));
+    assert!(!add_one.finished());
 }
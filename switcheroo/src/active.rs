@@ -0,0 +1,164 @@
+// Keeps track of the stack the current thread is running on.
+//
+// Each switch into a generator records the top of the generator's stack and restores the previous
+// value once the execution comes back (see `Generator::switch`). This makes it possible to tell how
+// much stack is left without knowing which generator is running.
+//
+// Next to the top, the stack limit of the running generator is kept. JIT compiled code can load it
+// (see `stack_limit_ptr`) and compare the stack pointer against it in function prologues, to trap
+// before the guard region is hit.
+
+use std::cell::Cell;
+
+use crate::stack::StackBuilder;
+use crate::{Generator, GeneratorSend, GeneratorState};
+
+/// The stack of the innermost running generator, both values are 0 if no generator is running.
+#[derive(Clone, Copy)]
+pub(crate) struct Active {
+    pub(crate) top: usize,
+    pub(crate) limit: usize,
+}
+
+struct Current {
+    top: Cell<usize>,
+    limit: Cell<usize>,
+}
+
+thread_local!(
+    static CURRENT: Current = const {
+        Current {
+            top: Cell::new(0),
+            limit: Cell::new(0),
+        }
+    };
+);
+
+/// Marks the given stack as the active one. Returns the previously active stack, that needs to be
+/// restored with `leave`.
+#[inline(always)]
+pub(crate) fn enter(stack: Active) -> Active {
+    CURRENT.with(|current| Active {
+        top: current.top.replace(stack.top),
+        limit: current.limit.replace(stack.limit),
+    })
+}
+
+/// Restores the stack that was active before the matching `enter` call.
+#[inline(always)]
+pub(crate) fn leave(previous: Active) {
+    CURRENT.with(|current| {
+        current.top.set(previous.top);
+        current.limit.set(previous.limit);
+    })
+}
+
+/// Returns the stack limit of the generator that is currently running on this thread, or `None`
+/// if the thread is not running on a switcheroo stack.
+///
+/// The limit is the [top](stack/trait.Stack.html#tymethod.top) of the generator's stack, right
+/// after its guard region, plus the slack set with
+/// [Generator::set_stack_limit_slack](struct.Generator.html#method.set_stack_limit_slack).
+/// Code that keeps the stack pointer above the limit never runs into the guard region.
+///
+/// With the `thread` backend generators run on the stacks of their own threads and this is always
+/// `None` inside of them.
+pub fn stack_limit() -> Option<usize> {
+    match CURRENT.with(|current| current.limit.get()) {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Returns a pointer to the current thread's copy of the [stack limit](fn.stack_limit.html).
+///
+/// The value behind it is updated on every switch into and out of a generator, so JIT compiled
+/// code can load it in function prologues and compare the stack pointer against it. It's 0 while no
+/// generator is running. The pointer is valid as long as the thread is alive. Generators can move
+/// between threads while they are suspended (e.g. inside of an async executor), the pointer needs
+/// to be fetched again after each suspension in that case.
+pub fn stack_limit_ptr() -> *const usize {
+    CURRENT.with(|current| current.limit.as_ptr() as *const usize)
+}
+
+/// Returns the number of bytes left on the stack of the generator that is currently running on
+/// this thread, or `None` if the thread is not running on a switcheroo stack. Like the
+/// [stack limit](fn.stack_limit.html) it's always `None` with the `thread` backend.
+///
+/// ```
+/// use switcheroo::stack::*;
+/// use switcheroo::{remaining_stack, Generator, GeneratorState};
+///
+/// assert_eq!(remaining_stack(), None);
+/// let stack = EightMbStack::new().unwrap();
+/// let mut generator = Generator::new(stack, |yielder, ()| {
+///     yielder.suspend(remaining_stack());
+/// })
+/// .unwrap();
+/// let remaining = generator.resume(()).unwrap();
+/// # #[cfg(not(feature = "thread"))]
+/// assert!(matches!(remaining, GeneratorState::Yielded(Some(r)) if r > 7 * 1024 * 1024));
+/// ```
+#[inline(never)]
+pub fn remaining_stack() -> Option<usize> {
+    let top = CURRENT.with(|current| current.top.get());
+    if top == 0 {
+        return None;
+    }
+    let marker = 0u8;
+    let sp = std::hint::black_box(&marker) as *const u8 as usize;
+    Some(sp.saturating_sub(top))
+}
+
+/// Runs `f` on a new stack of `stack_size` bytes if less than `red_zone` bytes are left on the
+/// current stack, otherwise `f` runs right away. Deeply recursive code can call it regularly to
+/// keep growing its stack instead of overflowing it.
+///
+/// Outside of generators and with the `thread` backend the remaining stack is unknown, `f` always
+/// runs on the current stack.
+///
+/// Panics if the new stack can't be created or if `f` faults while running on it. Panics inside
+/// of `f` are propagated to the caller.
+///
+/// ```
+/// use switcheroo::maybe_grow;
+///
+/// fn sum(n: u64) -> u64 {
+///     maybe_grow(32 * 1024, 1024 * 1024, || if n == 0 { 0 } else { n + sum(n - 1) })
+/// }
+/// # // The thread backend can't tell how much stack is left, the recursion would overflow.
+/// # #[cfg(not(feature = "thread"))]
+/// # {
+/// # use switcheroo::{Generator, GeneratorState, stack::*};
+/// # let mut generator = Generator::new(OneMbStack::new().unwrap(), |yielder, ()| {
+/// #     yielder.suspend(sum(100_000));
+/// # })
+/// # .unwrap();
+/// # assert_eq!(generator.resume(()), Ok(GeneratorState::Yielded(5_000_050_000)));
+/// # }
+/// ```
+pub fn maybe_grow<R, F: FnOnce() -> R + GeneratorSend>(
+    red_zone: usize,
+    stack_size: usize,
+    f: F,
+) -> R {
+    match remaining_stack() {
+        Some(remaining) if remaining < red_zone => grow(stack_size, f),
+        _ => f(),
+    }
+}
+
+#[inline(never)]
+fn grow<R, F: FnOnce() -> R + GeneratorSend>(stack_size: usize, f: F) -> R {
+    let stack = StackBuilder::new()
+        .size(stack_size)
+        .build()
+        .expect("failed to create a new stack");
+    let mut generator: Generator<(), (), R, _> =
+        Generator::new(stack, |_yielder, ()| f()).expect("failed to create a new stack");
+    match generator.resume(()) {
+        Ok(GeneratorState::Complete(result)) => result,
+        Ok(GeneratorState::Yielded(())) => unreachable!(),
+        Err(fault) => panic!("{}", fault),
+    }
+}
@@ -2,13 +2,15 @@
 // idea behind all of them.
 //
 // At the core of the implementation there are 3 functions:
-// * `init(stack: Stack, f: unsafe extern "C" fn(usize, *mut  usize))`
+// * `init(stack: Stack, bottom: *mut usize, f: unsafe extern "C" fn(usize, *mut  usize))`
 // * `swap_and_link_stacks(arg: usize, new_sp: *mut usize, sp: *mut usize) -> (usize, *mut usize)`
 // * `swap(arg: usize, new_sp: *mut usize, sp: *mut usize) -> (usize, *mut usize)`
 //
 // ### init
 // `init` takes a **stack** and a **pointer to a function**. It will prepare the stack so it is ready
-// to be switched to. Once we switch to it the function we set up here will be called.
+// to be switched to. Once we switch to it the function we set up here will be called. The initial
+// frame is placed below `bottom`, the generator keeps its closure between `bottom` and the real
+// bottom of the stack until the stacks are linked.
 //
 // Unix and Windows operating systems require different stack setups. Here is an illustration on how
 // the stacks look after the call to `init`:
@@ -67,18 +69,115 @@
 // Notice that the instruction pointer points to a cryptic **Trampoline 2** function and not to the
 // passed in **Function**. Trampoline 1 and 2 contain some extra assembler information so that it's
 // possible to re-create a backtrace across contexts if we panic inside the new context.
+//
+// ### return_to
+// Unix only (currently only on Linux). It's called from a signal handler when the code running on a
+// generator's stack faults. It rewrites the registers of the interrupted context to the values that
+// the last `swap` into the generator saved on the caller's stack. Once the signal handler returns,
+// the execution continues in the caller like the `swap` call returned normally.
+//
+// ### Floating point state
+// With the `fp-state` feature (on by default) the assembly backends save the floating point control
+// state in the stack frame of each `swap` call and restore it once the execution switches back. The
+// rounding mode or flush-to-zero setting of one context doesn't leak into another one this way. A new
+// generator starts with the state of the code that first resumed it. The layout of the values `swap`
+// pushes doesn't change, so `return_to` restores the state too. `swapcontext` and separate threads
+// already keep the state apart.
+//
+// ### ucontext
+// With the `ucontext` feature all Unix targets use `makecontext`/`swapcontext` instead of the
+// hand-written backends. It's a lot slower, but works on any libc that implements them.
+//
+// ### thread
+// The `thread` feature replaces all other backends. Each generator runs on its own thread and `swap`
+// hands the execution over between threads, without switching any stacks. Once a generator is
+// dropped `exit` lets its thread return from the function passed to `init`. Its `init` returns a
+// `Result`, because spawning the thread can fail.
 
-#[cfg(all(target_family = "unix", target_arch = "x86_64"))]
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "x86_64"
+))]
 mod unix_x64;
-#[cfg(all(target_family = "unix", target_arch = "x86_64"))]
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "x86_64"
+))]
 pub use self::unix_x64::*;
 
-#[cfg(all(target_family = "unix", target_arch = "aarch64"))]
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "aarch64"
+))]
 mod unix_aarch64;
-#[cfg(all(target_family = "unix", target_arch = "aarch64"))]
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "aarch64"
+))]
 pub use self::unix_aarch64::*;
 
-#[cfg(all(target_family = "windows", target_arch = "x86_64"))]
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "riscv64"
+))]
+mod unix_riscv64;
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "riscv64"
+))]
+pub use self::unix_riscv64::*;
+
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "x86"
+))]
+mod unix_x86;
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "x86"
+))]
+pub use self::unix_x86::*;
+
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "arm"
+))]
+mod unix_arm;
+#[cfg(all(
+    target_family = "unix",
+    not(any(feature = "ucontext", feature = "thread")),
+    target_arch = "arm"
+))]
+pub use self::unix_arm::*;
+
+#[cfg(all(target_family = "unix", feature = "ucontext", not(feature = "thread")))]
+mod ucontext;
+#[cfg(all(target_family = "unix", feature = "ucontext", not(feature = "thread")))]
+pub use self::ucontext::*;
+
+#[cfg(feature = "thread")]
+mod thread;
+#[cfg(feature = "thread")]
+pub use self::thread::*;
+
+#[cfg(all(
+    target_family = "windows",
+    not(feature = "thread"),
+    target_arch = "x86_64"
+))]
 mod windows_x64;
-#[cfg(all(target_family = "windows", target_arch = "x86_64"))]
+#[cfg(all(
+    target_family = "windows",
+    not(feature = "thread"),
+    target_arch = "x86_64"
+))]
 pub use self::windows_x64::*;
@@ -0,0 +1,127 @@
+// Context switches emulated with threads, enabled with the `thread` feature.
+//
+// Each generator runs on its own OS thread, but only one thread takes part in the execution at a
+// time: `swap` hands the argument over to the target thread and blocks the current one until
+// another `swap` hands the execution back. No stack is switched, so this works in environments that
+// can't follow stack switches, like Miri or the sanitizers. The memory of the generator's stack is
+// not used, the thread gets its own stack of the same size.
+//
+// Instead of a stack pointer the swap functions pass around a pointer to the `Context` of a thread.
+// It's kept alive by the thread itself, a generator's thread exits once `exit` is called.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::{stack, Error};
+
+struct Context {
+    // The argument and the context of the `swap` call that handed the execution over.
+    state: Mutex<Option<(usize, usize)>>,
+    resumed: Condvar,
+    // Only set for the threads of generators.
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Context {
+    fn new() -> Self {
+        Context {
+            state: Mutex::new(None),
+            resumed: Condvar::new(),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Hands the execution over to the thread waiting on this context.
+    fn resume(&self, arg: usize, from: usize) {
+        let mut state = self.state.lock().unwrap();
+        *state = Some((arg, from));
+        self.resumed.notify_one();
+    }
+
+    /// Blocks until the execution is handed over to this context.
+    fn wait(&self) -> (usize, usize) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.take() {
+                Some(resumed) => return resumed,
+                None => state = self.resumed.wait(state).unwrap(),
+            }
+        }
+    }
+}
+
+thread_local!(
+    // The context of the current thread, it's created the first time the thread switches.
+    static CURRENT: RefCell<Option<Arc<Context>>> = const { RefCell::new(None) };
+);
+
+fn current() -> Arc<Context> {
+    CURRENT.with(|current| {
+        current
+            .borrow_mut()
+            .get_or_insert_with(|| Arc::new(Context::new()))
+            .clone()
+    })
+}
+
+// The thread waiting on the context keeps it alive, this takes another reference to it.
+unsafe fn context(sp: *mut usize) -> Arc<Context> {
+    let context = sp as *const Context;
+    Arc::increment_strong_count(context);
+    Arc::from_raw(context)
+}
+
+pub unsafe fn init<S: stack::Stack>(
+    stack: &S,
+    _bottom: *mut usize,
+    f: unsafe extern "C" fn(usize, *mut usize),
+) -> Result<*mut usize, Error> {
+    let context = Arc::new(Context::new());
+    let thread_context = context.clone();
+    let thread = thread::Builder::new()
+        .stack_size(stack.size())
+        .spawn(move || {
+            let (arg, from) = thread_context.wait();
+            CURRENT.with(|current| *current.borrow_mut() = Some(thread_context));
+            // Returns once the generator is dropped.
+            unsafe { f(arg, from as *mut usize) };
+        })?;
+    *context.thread.lock().unwrap() = Some(thread);
+    Ok(Arc::as_ptr(&context) as *mut usize)
+}
+
+/// There are no stacks to link, this is the same as `swap`.
+#[inline(always)]
+pub unsafe fn swap_and_link_stacks(
+    arg: usize,
+    new_sp: *mut usize,
+    _sp: *const usize,
+) -> (usize, *mut usize) {
+    swap(arg, new_sp)
+}
+
+/// Swap between two threads.
+/// `new_sp` points to the context of the thread we are handing the execution over to, it was
+/// either returned by `init` or by a previous `swap`.
+///
+/// Returns the `arg` and the context of the `swap` call that handed the execution back.
+pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
+    let current = current();
+    context(new_sp).resume(arg, Arc::as_ptr(&current) as usize);
+    let (arg, from) = current.wait();
+    (arg, from as *mut usize)
+}
+
+/// Resumes the thread of a generator that finished or never started with a 0 `arg`, this lets it
+/// return from the generator's function. Blocks until the thread exited.
+pub unsafe fn exit(sp: *mut usize) {
+    let context = context(sp);
+    let thread = context.thread.lock().unwrap().take();
+    context.resume(0, 0);
+    drop(context);
+    if let Some(thread) = thread {
+        // The generator's function catches all panics.
+        thread.join().unwrap();
+    }
+}
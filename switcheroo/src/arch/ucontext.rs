@@ -0,0 +1,118 @@
+// A fallback for targets without a hand-written backend, enabled with the `ucontext` feature.
+//
+// Instead of a stack pointer the swap functions pass around a pointer to a `Context`. The context of
+// a new generator is placed at the bottom of its stack by `init`, all other contexts live in the
+// stack frame of the `swap` call that suspended the execution. Before switching, `swap` stores the
+// argument and a pointer to its own context inside of the target context, this is where the
+// resumed `swap` call picks them up.
+//
+// Every switch goes through libc and saves/restores the signal mask with a system call, this makes
+// it a lot slower than the hand-written backends. The stacks are not linked, backtraces stop at the
+// start of the generator.
+
+use core::mem::{size_of, MaybeUninit};
+use core::ptr;
+
+use libc::{c_int, c_void, ucontext_t};
+
+use crate::stack;
+
+// Not all targets that define `ucontext_t` in the libc crate also declare the functions.
+extern "C" {
+    fn getcontext(ucp: *mut ucontext_t) -> c_int;
+    #[cfg(feature = "std")]
+    fn setcontext(ucp: *const ucontext_t) -> c_int;
+    fn makecontext(ucp: *mut ucontext_t, func: extern "C" fn(), argc: c_int, ...);
+    fn swapcontext(oucp: *mut ucontext_t, ucp: *const ucontext_t) -> c_int;
+}
+
+struct Context {
+    ucontext: ucontext_t,
+    // The argument and the context of the `swap` call that switched to this context.
+    arg: usize,
+    from: *mut Context,
+    // The function a new stack starts with.
+    f: unsafe extern "C" fn(usize, *mut usize),
+}
+
+pub unsafe fn init<S: stack::Stack>(
+    stack: &S,
+    bottom: *mut usize,
+    f: unsafe extern "C" fn(usize, *mut usize),
+) -> *mut usize {
+    // `makecontext` only passes `int` arguments to the function, the address of the context is
+    // split into two halves.
+    unsafe extern "C" fn entry(low: u32, high: u32) {
+        let context = (((high as u64) << 32) | low as u64) as usize as *mut Context;
+        ((*context).f)((*context).arg, (*context).from as *mut usize);
+        // The (generator_wrapper) function never returns.
+    }
+
+    // Place the context at the bottom of the stack, the rest is used by the new context.
+    let context =
+        ((bottom as usize - size_of::<Context>()) & !(stack::STACK_ALIGNMENT - 1)) as *mut Context;
+
+    let ucontext = ptr::addr_of_mut!((*context).ucontext);
+    assert_eq!(getcontext(ucontext), 0, "getcontext failed");
+    (*ucontext).uc_stack.ss_sp = stack.top() as *mut c_void;
+    (*ucontext).uc_stack.ss_size = context as usize - stack.top() as usize;
+    (*ucontext).uc_stack.ss_flags = 0;
+    (*ucontext).uc_link = ptr::null_mut();
+    ptr::addr_of_mut!((*context).f).write(f);
+
+    let address = context as usize as u64;
+    makecontext(
+        ucontext,
+        core::mem::transmute::<unsafe extern "C" fn(u32, u32), extern "C" fn()>(entry),
+        2,
+        address as u32,
+        (address >> 32) as u32,
+    );
+
+    context as *mut usize
+}
+
+/// The stacks can't be linked, this is the same as `swap`.
+#[inline(always)]
+pub unsafe fn swap_and_link_stacks(
+    arg: usize,
+    new_sp: *mut usize,
+    _sp: *const usize,
+) -> (usize, *mut usize) {
+    swap(arg, new_sp)
+}
+
+/// Swap between two contexts.
+/// `new_sp` points to the context we are jumping to, it was either returned by `init` or by a
+/// previous `swap`.
+///
+/// Returns the `arg` and the context of the `swap` call that switched back to this one.
+#[inline(never)]
+pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
+    let mut context = MaybeUninit::<Context>::uninit();
+    let context = context.as_mut_ptr();
+    let target = new_sp as *mut Context;
+    (*target).arg = arg;
+    (*target).from = context;
+
+    let result = swapcontext(
+        ptr::addr_of_mut!((*context).ucontext),
+        ptr::addr_of!((*target).ucontext),
+    );
+    debug_assert_eq!(result, 0, "swapcontext failed");
+
+    ((*context).arg, (*context).from as *mut usize)
+}
+
+/// Continues the execution after the `swap` call that saved `sp`, like it returned `arg`.
+///
+/// Unlike the other backends this doesn't return, it jumps straight out of the signal handler. The
+/// signal mask saved by `swap` is restored with the rest of the context. The current stack is
+/// abandoned.
+#[cfg(feature = "std")]
+pub unsafe fn return_to(_context: *mut c_void, sp: *mut usize, arg: usize) {
+    let context = sp as *mut Context;
+    (*context).arg = arg;
+    (*context).from = ptr::null_mut();
+    setcontext(ptr::addr_of!((*context).ucontext));
+}
@@ -1,8 +1,9 @@
 use crate::stack;
-use core::arch::asm;
+use core::arch::{asm, naked_asm};
 
 pub unsafe fn init<S: stack::Stack>(
-    stack: &S,
+    _stack: &S,
+    bottom: *mut usize,
     f: unsafe extern "C" fn(usize, *mut usize),
 ) -> *mut usize {
     unsafe fn push(mut sp: *mut usize, val: usize) -> *mut usize {
@@ -11,30 +12,32 @@ pub unsafe fn init<S: stack::Stack>(
         sp
     }
 
-    let mut sp = stack.bottom();
+    let mut sp = bottom;
 
     // Save the (generator_wrapper) function on the stack.
     sp = push(sp, f as usize);
     sp = push(sp, 0xdeaddeaddead0cfa);
 
-    #[naked]
+    #[unsafe(naked)]
     unsafe extern "C" fn trampoline() {
-        asm!(
+        naked_asm!(
+            ".cfi_startproc",
             // Stops unwinding/backtracing at this function.
             ".cfi_undefined lr",
             "ldr x2, [sp, #8]",
             "blr x2",
-            options(noreturn)
+            ".cfi_endproc",
         )
     }
 
     // Save frame pointer
     let frame = sp;
-    sp = push(sp, trampoline as usize);
+    sp = push(sp, trampoline as *const () as usize);
     sp = push(sp, frame as usize);
 
     // x18 & x 19
-    sp = push(sp, 0); sp = push(sp, 0);
+    sp = push(sp, 0);
+    sp = push(sp, 0);
 
     sp
 }
@@ -47,6 +50,8 @@ pub unsafe fn swap_and_link_stacks(
 ) -> (usize, *mut usize) {
     let ret_val: usize;
     let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
 
     asm!(
         "adr lr, 1337f",
@@ -83,6 +88,9 @@ pub unsafe fn swap_and_link_stacks(
         out("v28") _, out("v29") _, out("v30") _, out("v31") _,
     );
 
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
     (ret_val, ret_sp)
 }
 
@@ -90,6 +98,8 @@ pub unsafe fn swap_and_link_stacks(
 pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
     let ret_val: usize;
     let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
 
     asm!(
         "adr lr, 1337f",
@@ -124,5 +134,42 @@ pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
         out("v28") _, out("v29") _, out("v30") _, out("v31") _,
     );
 
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
     (ret_val, ret_sp)
 }
+
+// Saves FPCR (rounding mode, flush-to-zero and trap enables), `swap` restores it once the
+// execution switches back.
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn save_fp_state() -> usize {
+    let fpcr: usize;
+    asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack, preserves_flags));
+    fpcr
+}
+
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn restore_fp_state(fpcr: usize) {
+    asm!("msr fpcr, {}", in(reg) fpcr, options(nomem, nostack, preserves_flags));
+}
+
+/// Rewrites the `context` of a signal handler, so that once the handler returns the execution
+/// continues after the `swap` call that saved `sp`, like it returned `arg`.
+///
+/// The current stack is abandoned.
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub unsafe fn return_to(context: *mut libc::c_void, sp: *mut usize, arg: usize) {
+    let mcontext = &mut (*(context as *mut libc::ucontext_t)).uc_mcontext;
+    // Pop the values pushed by `swap`.
+    mcontext.regs[18] = *sp as u64;
+    mcontext.regs[19] = *sp.add(1) as u64;
+    mcontext.regs[29] = *sp.add(2) as u64;
+    mcontext.regs[30] = *sp.add(3) as u64;
+    mcontext.pc = *sp.add(3) as u64;
+    mcontext.sp = sp.add(4) as u64;
+    mcontext.regs[0] = arg as u64;
+    mcontext.regs[1] = 0;
+}
@@ -0,0 +1,173 @@
+use crate::stack;
+use core::arch::{asm, naked_asm};
+
+pub unsafe fn init<S: stack::Stack>(
+    _stack: &S,
+    bottom: *mut usize,
+    f: unsafe extern "C" fn(usize, *mut usize),
+) -> *mut usize {
+    unsafe fn push(mut sp: *mut usize, val: usize) -> *mut usize {
+        sp = sp.offset(-1);
+        *sp = val;
+        sp
+    }
+
+    let mut sp = bottom;
+
+    // Save the (generator_wrapper) function on the stack.
+    sp = push(sp, f as usize);
+    sp = push(sp, 0xdead0cfa);
+
+    #[unsafe(naked)]
+    unsafe extern "C" fn trampoline() {
+        naked_asm!(
+            ".cfi_startproc",
+            // Stops unwinding/backtracing at this function.
+            ".cfi_undefined lr",
+            "ldr r2, [sp, #4]",
+            "blx r2",
+            ".cfi_endproc",
+        )
+    }
+
+    // Save frame pointer
+    let frame = sp;
+    sp = push(sp, trampoline as *const () as usize);
+    sp = push(sp, frame as usize);
+
+    // r6 & r7
+    sp = push(sp, 0);
+    sp = push(sp, 0);
+
+    sp
+}
+
+// r6 is used internally by LLVM and r7 or r11 (depending on the instruction set) hold the frame
+// pointer. None of them can be marked as output registers, they are saved on the stack instead.
+// The return address is taken from `bl`, which sets its lowest bit in Thumb code. This way `bx`
+// continues in the instruction set the code was compiled to.
+
+#[inline(always)]
+pub unsafe fn swap_and_link_stacks(
+    arg: usize,
+    new_sp: *mut usize,
+    sp: *const usize,
+) -> (usize, *mut usize) {
+    let ret_val: usize;
+    let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
+
+    asm!(
+        "bl 2f",
+        "b 1337f",
+        "2:",
+        "push {{r6, r7, r11, lr}}",
+        "mov r1, sp",
+        "str r1, [r3, #-8]",
+        "mov sp, r2",
+        "pop {{r6, r7, r11, lr}}",
+        "bx lr",
+        "1337:",
+
+        inout("r3") sp => _,
+        inout("r2") new_sp => _,
+        inout("r0") arg => ret_val,
+        out("r1") ret_sp,
+
+        out("r4") _, out("r5") _, out("r8") _, out("r9") _,
+        out("r10") _, out("r12") _, out("lr") _,
+
+        out("d0") _, out("d1") _, out("d2") _, out("d3") _,
+        out("d4") _, out("d5") _, out("d6") _, out("d7") _,
+        out("d8") _, out("d9") _, out("d10") _, out("d11") _,
+        out("d12") _, out("d13") _, out("d14") _, out("d15") _,
+    );
+
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
+    (ret_val, ret_sp)
+}
+
+#[inline(always)]
+pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
+    let ret_val: usize;
+    let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
+
+    asm!(
+        "bl 2f",
+        "b 1337f",
+        "2:",
+        "push {{r6, r7, r11, lr}}",
+        "mov r1, sp",
+        "mov sp, r2",
+        "pop {{r6, r7, r11, lr}}",
+        "bx lr",
+        "1337:",
+
+        inout("r2") new_sp => _,
+        inout("r0") arg => ret_val,
+        out("r1") ret_sp, out("r3") _,
+
+        out("r4") _, out("r5") _, out("r8") _, out("r9") _,
+        out("r10") _, out("r12") _, out("lr") _,
+
+        out("d0") _, out("d1") _, out("d2") _, out("d3") _,
+        out("d4") _, out("d5") _, out("d6") _, out("d7") _,
+        out("d8") _, out("d9") _, out("d10") _, out("d11") _,
+        out("d12") _, out("d13") _, out("d14") _, out("d15") _,
+    );
+
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
+    (ret_val, ret_sp)
+}
+
+// Saves FPSCR (rounding mode, flush-to-zero, trap enables and the status flags), `swap` restores
+// it once the execution switches back.
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn save_fp_state() -> usize {
+    let fpscr: usize;
+    asm!("vmrs {}, fpscr", out(reg) fpscr, options(nomem, nostack, preserves_flags));
+    fpscr
+}
+
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn restore_fp_state(fpscr: usize) {
+    asm!("vmsr fpscr, {}", in(reg) fpscr, options(nomem, nostack, preserves_flags));
+}
+
+/// Rewrites the `context` of a signal handler, so that once the handler returns the execution
+/// continues after the `swap` call that saved `sp`, like it returned `arg`.
+///
+/// The current stack is abandoned.
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub unsafe fn return_to(context: *mut libc::c_void, sp: *mut usize, arg: usize) {
+    // Thumb state and If-Then execution state bits.
+    const CPSR_THUMB: libc::c_ulong = 1 << 5;
+    const CPSR_IT: libc::c_ulong = 0x0600_fc00;
+
+    let mcontext = &mut (*(context as *mut libc::ucontext_t)).uc_mcontext;
+    // Pop the values pushed by `swap`.
+    mcontext.arm_r6 = *sp as libc::c_ulong;
+    mcontext.arm_r7 = *sp.add(1) as libc::c_ulong;
+    mcontext.arm_fp = *sp.add(2) as libc::c_ulong;
+    let lr = *sp.add(3) as libc::c_ulong;
+    mcontext.arm_lr = lr;
+    mcontext.arm_pc = lr & !1;
+    mcontext.arm_sp = sp.add(4) as libc::c_ulong;
+    mcontext.arm_r0 = arg as libc::c_ulong;
+    mcontext.arm_r1 = 0;
+    // The fault could have happened in either instruction set, `swap` continues in the one its
+    // return address is tagged with.
+    mcontext.arm_cpsr &= !(CPSR_THUMB | CPSR_IT);
+    if lr & 1 != 0 {
+        mcontext.arm_cpsr |= CPSR_THUMB;
+    }
+}
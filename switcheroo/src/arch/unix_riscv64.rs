@@ -0,0 +1,196 @@
+use crate::stack;
+use core::arch::{asm, naked_asm};
+
+pub unsafe fn init<S: stack::Stack>(
+    _stack: &S,
+    bottom: *mut usize,
+    f: unsafe extern "C" fn(usize, *mut usize),
+) -> *mut usize {
+    unsafe fn push(mut sp: *mut usize, val: usize) -> *mut usize {
+        sp = sp.offset(-1);
+        *sp = val;
+        sp
+    }
+
+    let mut sp = bottom;
+
+    // Save the (generator_wrapper) function on the stack.
+    sp = push(sp, f as usize);
+    sp = push(sp, 0xdeaddeaddead0cfa);
+
+    #[unsafe(naked)]
+    unsafe extern "C" fn trampoline() {
+        naked_asm!(
+            ".cfi_startproc",
+            // Stops unwinding/backtracing at this function.
+            ".cfi_undefined ra",
+            "ld t0, 8(sp)",
+            "jalr t0",
+            ".cfi_endproc",
+        )
+    }
+
+    // Save frame pointer
+    let frame = sp;
+    // Keeps the stack 16 byte aligned, `swap` pops 4 values.
+    sp = push(sp, 0);
+    sp = push(sp, trampoline as *const () as usize);
+    sp = push(sp, frame as usize);
+
+    // Set s1 starting value to 0
+    sp = push(sp, 0);
+
+    sp
+}
+
+#[inline(always)]
+pub unsafe fn swap_and_link_stacks(
+    arg: usize,
+    new_sp: *mut usize,
+    sp: *const usize,
+) -> (usize, *mut usize) {
+    let ret_val: usize;
+    let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
+
+    asm!(
+        // Save the continuation spot after we jump back here to be after this asm block.
+        "lla t0, 1337f",
+        "addi sp, sp, -32",
+        "sd t0, 16(sp)",
+        // Save the frame pointer as it can't be marked as an output register.
+        "sd s0, 8(sp)",
+        // s1 is used internally by LLVM and can't be marked as an output register.
+        "sd s1, 0(sp)",
+        // Link stacks by swapping the CFA value
+        "sd sp, -16(a3)",
+        // Set the current pointer as the 2nd element (a1) of the function we are jumping to.
+        "mv a1, sp",
+        // Change the stack pointer to the passed value.
+        "mv sp, a2",
+        "ld s1, 0(sp)",
+        "ld s0, 8(sp)",
+        "ld t0, 16(sp)",
+        "addi sp, sp, 32",
+        "jr t0",
+        "1337:",
+        // Mark all registers as clobbered as we don't know what the code we are jumping to is going to use.
+        // The compiler will optimise this out and just save the registers it actually knows it must.
+        inout("a3") sp => _,
+        inout("a2") new_sp => _,
+        inout("a0") arg => ret_val, // 1st argument to called function
+        out("a1") ret_sp, // 2nd argument to called function
+
+        out("ra") _, out("t0") _, out("t1") _, out("t2") _,
+        out("t3") _, out("t4") _, out("t5") _, out("t6") _,
+        out("a4") _, out("a5") _, out("a6") _, out("a7") _,
+        out("s2") _, out("s3") _, out("s4") _, out("s5") _,
+        out("s6") _, out("s7") _, out("s8") _, out("s9") _,
+        out("s10") _, out("s11") _,
+
+        out("ft0") _, out("ft1") _, out("ft2") _, out("ft3") _,
+        out("ft4") _, out("ft5") _, out("ft6") _, out("ft7") _,
+        out("ft8") _, out("ft9") _, out("ft10") _, out("ft11") _,
+        out("fa0") _, out("fa1") _, out("fa2") _, out("fa3") _,
+        out("fa4") _, out("fa5") _, out("fa6") _, out("fa7") _,
+        out("fs0") _, out("fs1") _, out("fs2") _, out("fs3") _,
+        out("fs4") _, out("fs5") _, out("fs6") _, out("fs7") _,
+        out("fs8") _, out("fs9") _, out("fs10") _, out("fs11") _,
+    );
+
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
+    (ret_val, ret_sp)
+}
+
+/// Swap between two stacks.
+/// `new_sp` is the stack we are jumping to. This stack needs to have at the top:
+/// 1. The value of s1
+/// 2. Stack frame pointer
+/// 3. Pointer to the next instruction to execute on the new stack
+/// 4. Padding, keeping the stack 16 byte aligned
+///
+/// If the pointer points to an `extern "C"` function then the `arg` element is forwarded to it
+/// through the `a0` register.
+#[inline(always)]
+pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
+    let ret_val: usize;
+    let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
+
+    asm!(
+        "lla t0, 1337f",
+        "addi sp, sp, -32",
+        "sd t0, 16(sp)",
+        "sd s0, 8(sp)",
+        "sd s1, 0(sp)",
+        "mv a1, sp",
+        "mv sp, a2",
+        "ld s1, 0(sp)",
+        "ld s0, 8(sp)",
+        "ld t0, 16(sp)",
+        "addi sp, sp, 32",
+        "jr t0",
+        "1337:",
+
+        inout("a2") new_sp => _,
+        inout("a0") arg => ret_val,
+        out("a1") ret_sp, out("a3") _,
+
+        out("ra") _, out("t0") _, out("t1") _, out("t2") _,
+        out("t3") _, out("t4") _, out("t5") _, out("t6") _,
+        out("a4") _, out("a5") _, out("a6") _, out("a7") _,
+        out("s2") _, out("s3") _, out("s4") _, out("s5") _,
+        out("s6") _, out("s7") _, out("s8") _, out("s9") _,
+        out("s10") _, out("s11") _,
+
+        out("ft0") _, out("ft1") _, out("ft2") _, out("ft3") _,
+        out("ft4") _, out("ft5") _, out("ft6") _, out("ft7") _,
+        out("ft8") _, out("ft9") _, out("ft10") _, out("ft11") _,
+        out("fa0") _, out("fa1") _, out("fa2") _, out("fa3") _,
+        out("fa4") _, out("fa5") _, out("fa6") _, out("fa7") _,
+        out("fs0") _, out("fs1") _, out("fs2") _, out("fs3") _,
+        out("fs4") _, out("fs5") _, out("fs6") _, out("fs7") _,
+        out("fs8") _, out("fs9") _, out("fs10") _, out("fs11") _,
+    );
+
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
+    (ret_val, ret_sp)
+}
+
+// Saves fcsr (rounding mode and accrued exception flags), `swap` restores it once the execution
+// switches back.
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn save_fp_state() -> usize {
+    let fcsr: usize;
+    asm!("frcsr {}", out(reg) fcsr, options(nomem, nostack, preserves_flags));
+    fcsr
+}
+
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn restore_fp_state(fcsr: usize) {
+    asm!("fscsr {}", in(reg) fcsr, options(nomem, nostack, preserves_flags));
+}
+
+/// Rewrites the `context` of a signal handler, so that once the handler returns the execution
+/// continues after the `swap` call that saved `sp`, like it returned `arg`.
+///
+/// The current stack is abandoned.
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub unsafe fn return_to(context: *mut libc::c_void, sp: *mut usize, arg: usize) {
+    let gregs = &mut (*(context as *mut libc::ucontext_t)).uc_mcontext.__gregs;
+    // Pop the values pushed by `swap`.
+    gregs[libc::REG_S1] = *sp as libc::c_ulong;
+    gregs[libc::REG_S0] = *sp.add(1) as libc::c_ulong;
+    gregs[libc::REG_PC] = *sp.add(2) as libc::c_ulong;
+    gregs[libc::REG_SP] = sp.add(4) as libc::c_ulong;
+    gregs[libc::REG_A0] = arg as libc::c_ulong;
+    gregs[libc::REG_A0 + 1] = 0;
+}
@@ -1,8 +1,9 @@
 use crate::stack;
-use core::arch::asm;
+use core::arch::{asm, naked_asm};
 
 pub unsafe fn init<S: stack::Stack>(
-    stack: &S,
+    _stack: &S,
+    bottom: *mut usize,
     f: unsafe extern "C" fn(usize, *mut usize),
 ) -> *mut usize {
     unsafe fn push(mut sp: *mut usize, val: usize) -> *mut usize {
@@ -11,25 +12,26 @@ pub unsafe fn init<S: stack::Stack>(
         sp
     }
 
-    let mut sp = stack.bottom();
+    let mut sp = bottom;
 
     // Save the (generator_wrapper) function on the stack.
     sp = push(sp, f as usize);
     sp = push(sp, 0xdeaddeaddead0cfa);
 
-    #[naked]
+    #[unsafe(naked)]
     unsafe extern "C" fn trampoline() {
-        asm!(
+        naked_asm!(
+            ".cfi_startproc",
             // Stops unwinding/backtracing at this function.
             ".cfi_undefined rip",
             "call [rsp + 8]",
-            options(noreturn)
+            ".cfi_endproc",
         )
     }
 
     // Save frame pointer
     let frame = sp;
-    sp = push(sp, trampoline as usize); // call instruction
+    sp = push(sp, trampoline as *const () as usize); // call instruction
     sp = push(sp, frame as usize);
 
     // Set rbx starting value to 0
@@ -46,6 +48,8 @@ pub unsafe fn swap_and_link_stacks(
 ) -> (usize, *mut usize) {
     let ret_val: usize;
     let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
 
     asm!(
         // Save the continuation spot after we jump back here to be after this asm block.
@@ -87,6 +91,9 @@ pub unsafe fn swap_and_link_stacks(
         out("xmm12") _, out("xmm13") _, out("xmm14") _, out("xmm15") _,
     );
 
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
     (ret_val, ret_sp)
 }
 
@@ -94,6 +101,7 @@ pub unsafe fn swap_and_link_stacks(
 /// `new_sp` is the stack we are jumping to. This stack needs to have at the top:
 /// 1. Stack frame pointer
 /// 2. Pointer to the next instruction to execute on the new stack
+///
 /// If the pointer points to an `extern "C"` function then the `arg` element is forwarded to it
 /// through the `rdi` register.
 ///
@@ -104,6 +112,8 @@ pub unsafe fn swap_and_link_stacks(
 pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
     let ret_val: usize;
     let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
 
     asm!(
         // Save the continuation spot after we jump back here to be after this asm block.
@@ -142,5 +152,54 @@ pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
         out("xmm12") _, out("xmm13") _, out("xmm14") _, out("xmm15") _,
     );
 
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
     (ret_val, ret_sp)
 }
+
+// Saves the floating point control state of the current context: the rounding modes, exception
+// masks and flush-to-zero bits of MXCSR and the x87 control word. `swap` restores it once the
+// execution switches back, so changes made by the other context don't leak into this one.
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn save_fp_state() -> (u32, u16) {
+    let mut mxcsr: u32 = 0;
+    let mut control: u16 = 0;
+    asm!(
+        "stmxcsr [{}]",
+        "fnstcw [{}]",
+        in(reg) &mut mxcsr,
+        in(reg) &mut control,
+        options(nostack, preserves_flags),
+    );
+    (mxcsr, control)
+}
+
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn restore_fp_state((mxcsr, control): (u32, u16)) {
+    asm!(
+        "ldmxcsr [{}]",
+        "fldcw [{}]",
+        in(reg) &mxcsr,
+        in(reg) &control,
+        options(nostack, preserves_flags, readonly),
+    );
+}
+
+/// Rewrites the `context` of a signal handler, so that once the handler returns the execution
+/// continues after the `swap` call that saved `sp`, like it returned `arg`.
+///
+/// The current stack is abandoned.
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub unsafe fn return_to(context: *mut libc::c_void, sp: *mut usize, arg: usize) {
+    let gregs = &mut (*(context as *mut libc::ucontext_t)).uc_mcontext.gregs;
+    // Pop the values pushed by `swap`.
+    gregs[libc::REG_RBX as usize] = *sp as libc::greg_t;
+    gregs[libc::REG_RBP as usize] = *sp.add(1) as libc::greg_t;
+    gregs[libc::REG_RIP as usize] = *sp.add(2) as libc::greg_t;
+    gregs[libc::REG_RSP as usize] = sp.add(3) as libc::greg_t;
+    gregs[libc::REG_RDI as usize] = arg as libc::greg_t;
+    gregs[libc::REG_RSI as usize] = 0;
+}
@@ -0,0 +1,187 @@
+use crate::stack;
+use core::arch::{asm, naked_asm};
+
+pub unsafe fn init<S: stack::Stack>(
+    _stack: &S,
+    bottom: *mut usize,
+    f: unsafe extern "C" fn(usize, *mut usize),
+) -> *mut usize {
+    unsafe fn push(mut sp: *mut usize, val: usize) -> *mut usize {
+        sp = sp.offset(-1);
+        *sp = val;
+        sp
+    }
+
+    let mut sp = bottom;
+
+    // Save the (generator_wrapper) function on the stack.
+    sp = push(sp, f as usize);
+    sp = push(sp, 0xdead0cfa);
+
+    #[unsafe(naked)]
+    unsafe extern "C" fn trampoline() {
+        naked_asm!(
+            ".cfi_startproc",
+            // Stops unwinding/backtracing at this function.
+            ".cfi_undefined eip",
+            // The C calling convention passes arguments on the stack, the swap functions leave
+            // them in registers. This also keeps the stack 16 byte aligned at the call.
+            "push ecx",
+            "push eax",
+            "call dword ptr [esp + 12]",
+            ".cfi_endproc",
+        )
+    }
+
+    // Save frame pointer
+    let frame = sp;
+    sp = push(sp, trampoline as *const () as usize); // call instruction
+    sp = push(sp, frame as usize);
+
+    // Set esi starting value to 0
+    sp = push(sp, 0);
+
+    sp
+}
+
+#[inline(always)]
+pub unsafe fn swap_and_link_stacks(
+    arg: usize,
+    new_sp: *mut usize,
+    sp: *const usize,
+) -> (usize, *mut usize) {
+    let ret_val: usize;
+    let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
+
+    asm!(
+        // Save the continuation spot after we jump back here to be after this asm block. There is
+        // no instruction pointer relative addressing on x86, `call` pushes the address of the
+        // following `jmp` instead.
+        "call 2f",
+        "jmp 1337f",
+        "2:",
+        // Save the frame pointer as it can't be marked as an output register.
+        "push ebp",
+        // esi is is used internally by LLVM and can't be marked as an output register.
+        "push esi",
+        // Link stacks by swapping the CFA value
+        "mov [edi - 8], esp",
+        // Set the current pointer as the 2nd argument (ecx) of the function we are jumping to.
+        "mov ecx, esp",
+        // Change the stack pointer to the passed value.
+        "mov esp, edx",
+        // Restore esi
+        "pop esi",
+        // Set the frame pointer according to the new stack.
+        "pop ebp",
+        // Get the next instruction to jump to.
+        "pop edi",
+        "jmp edi",
+        "1337:",
+        // Mark all registers as clobbered as we don't know what the code we are jumping to is going to use.
+        // The compiler will optimise this out and just save the registers it actually knows it must.
+        inout("edi") sp => _,
+        inout("edx") new_sp => _,
+        inout("eax") arg => ret_val, // 1st argument to called function
+        out("ecx") ret_sp, // 2nd argument to called function
+        out("ebx") _,
+
+        out("xmm0") _, out("xmm1") _, out("xmm2") _, out("xmm3") _,
+        out("xmm4") _, out("xmm5") _, out("xmm6") _, out("xmm7") _,
+    );
+
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
+    (ret_val, ret_sp)
+}
+
+/// Swap between two stacks.
+/// `new_sp` is the stack we are jumping to. This stack needs to have at the top:
+/// 1. The value of esi
+/// 2. Stack frame pointer
+/// 3. Pointer to the next instruction to execute on the new stack
+///
+/// If the pointer points to the trampoline set up by `init`, the `arg` element is forwarded to the
+/// `extern "C"` function through the `eax` register.
+#[inline(always)]
+pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
+    let ret_val: usize;
+    let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
+
+    asm!(
+        "call 2f",
+        "jmp 1337f",
+        "2:",
+        "push ebp",
+        "push esi",
+        "mov ecx, esp",
+        "mov esp, edx",
+        "pop esi",
+        "pop ebp",
+        "pop edi",
+        "jmp edi",
+        "1337:",
+
+        inout("edx") new_sp => _,
+        inout("eax") arg => ret_val,
+        out("ecx") ret_sp,
+        out("ebx") _, out("edi") _,
+
+        out("xmm0") _, out("xmm1") _, out("xmm2") _, out("xmm3") _,
+        out("xmm4") _, out("xmm5") _, out("xmm6") _, out("xmm7") _,
+    );
+
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
+    (ret_val, ret_sp)
+}
+
+// Saves MXCSR and the x87 control word, `swap` restores them once the execution switches back.
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn save_fp_state() -> (u32, u16) {
+    let mut mxcsr: u32 = 0;
+    let mut control: u16 = 0;
+    asm!(
+        "stmxcsr [{}]",
+        "fnstcw [{}]",
+        in(reg) &mut mxcsr,
+        in(reg) &mut control,
+        options(nostack, preserves_flags),
+    );
+    (mxcsr, control)
+}
+
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn restore_fp_state((mxcsr, control): (u32, u16)) {
+    asm!(
+        "ldmxcsr [{}]",
+        "fldcw [{}]",
+        in(reg) &mxcsr,
+        in(reg) &control,
+        options(nostack, preserves_flags, readonly),
+    );
+}
+
+/// Rewrites the `context` of a signal handler, so that once the handler returns the execution
+/// continues after the `swap` call that saved `sp`, like it returned `arg`.
+///
+/// The current stack is abandoned.
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub unsafe fn return_to(context: *mut libc::c_void, sp: *mut usize, arg: usize) {
+    let gregs = &mut (*(context as *mut libc::ucontext_t)).uc_mcontext.gregs;
+    // Pop the values pushed by `swap`.
+    gregs[libc::REG_ESI as usize] = *sp as libc::greg_t;
+    gregs[libc::REG_EBP as usize] = *sp.add(1) as libc::greg_t;
+    gregs[libc::REG_EIP as usize] = *sp.add(2) as libc::greg_t;
+    gregs[libc::REG_ESP as usize] = sp.add(3) as libc::greg_t;
+    gregs[libc::REG_EAX as usize] = arg as libc::greg_t;
+    gregs[libc::REG_ECX as usize] = 0;
+}
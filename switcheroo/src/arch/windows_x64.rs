@@ -1,8 +1,9 @@
 use crate::stack;
-use core::arch::asm;
+use core::arch::{asm, naked_asm};
 
 pub unsafe fn init<S: stack::Stack>(
     stack: &S,
+    bottom: *mut usize,
     f: unsafe extern "C" fn(usize, *mut usize),
 ) -> *mut usize {
     unsafe fn push(mut sp: *mut usize, val: usize) -> *mut usize {
@@ -11,16 +12,16 @@ pub unsafe fn init<S: stack::Stack>(
         sp
     }
 
-    let mut sp = stack.bottom();
+    let mut sp = bottom;
 
     // Save the (generator_wrapper) function on the stack.
     sp = push(sp, f as usize);
     sp = push(sp, 0xdeaddeaddead0cfa);
 
-    #[naked]
+    #[unsafe(naked)]
     #[no_mangle]
     unsafe extern "C" fn trampoline() {
-        asm!(
+        naked_asm!(
             // This directives will create unwind codes to link the two stacks together during stack traces.
             // The assembly was carefully crafted by a painfully long process of trial and error. For the most
             // part I was guessing how the stack tracing uses the Windows unwind codes and then went ahead and
@@ -63,13 +64,12 @@ pub unsafe fn init<S: stack::Stack>(
             "nop",
             "nop",
             ".seh_endproc",
-            options(noreturn)
         )
     }
 
     // Save frame pointer
     let frame = sp;
-    sp = push(sp, trampoline as usize + 4); //  "call [rsp + 8]" instruction
+    sp = push(sp, trampoline as *const () as usize + 4); //  "call [rsp + 8]" instruction
     sp = push(sp, frame as usize);
 
     // Set rbx starting value to 0
@@ -104,6 +104,8 @@ pub unsafe fn swap_and_link_stacks(
 ) -> (usize, *mut usize) {
     let ret_val: usize;
     let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
 
     asm!(
         // Save the continuation spot after we jump back here to be after this asm block.
@@ -170,6 +172,9 @@ pub unsafe fn swap_and_link_stacks(
         out("xmm12") _, out("xmm13") _, out("xmm14") _, out("xmm15") _,
     );
 
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
     (ret_val, ret_sp)
 }
 
@@ -177,6 +182,8 @@ pub unsafe fn swap_and_link_stacks(
 pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
     let ret_val: usize;
     let ret_sp: *mut usize;
+    #[cfg(feature = "fp-state")]
+    let fp_state = save_fp_state();
 
     asm!(
         // Save the continuation spot after we jump back here to be after this asm block.
@@ -239,5 +246,36 @@ pub unsafe fn swap(arg: usize, new_sp: *mut usize) -> (usize, *mut usize) {
         out("xmm12") _, out("xmm13") _, out("xmm14") _, out("xmm15") _,
     );
 
+    #[cfg(feature = "fp-state")]
+    restore_fp_state(fp_state);
+
     (ret_val, ret_sp)
 }
+
+// Saves MXCSR and the x87 control word, `swap` restores them once the execution switches back.
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn save_fp_state() -> (u32, u16) {
+    let mut mxcsr: u32 = 0;
+    let mut control: u16 = 0;
+    asm!(
+        "stmxcsr [{}]",
+        "fnstcw [{}]",
+        in(reg) &mut mxcsr,
+        in(reg) &mut control,
+        options(nostack, preserves_flags),
+    );
+    (mxcsr, control)
+}
+
+#[cfg(feature = "fp-state")]
+#[inline(always)]
+unsafe fn restore_fp_state((mxcsr, control): (u32, u16)) {
+    asm!(
+        "ldmxcsr [{}]",
+        "fldcw [{}]",
+        in(reg) &mxcsr,
+        in(reg) &control,
+        options(nostack, preserves_flags, readonly),
+    );
+}
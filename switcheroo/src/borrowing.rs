@@ -0,0 +1,225 @@
+use core::cell::Cell;
+use core::ptr::NonNull;
+
+use crate::{stack, Error, Fault, Generator, GeneratorSend, GeneratorState, Yielder};
+
+/// Names a type that borrows from its surroundings, so that a
+/// [BorrowingGenerator](struct.BorrowingGenerator.html) can be resumed with a mutable reference to
+/// it for any lifetime.
+///
+/// ```
+/// use std::task::Context;
+///
+/// struct ContextInput;
+///
+/// impl switcheroo::BorrowedInput for ContextInput {
+///     type Input<'r> = Context<'r>;
+/// }
+/// ```
+pub trait BorrowedInput {
+    /// The type resumed with, `'r` is the lifetime it borrows for.
+    type Input<'r>;
+}
+
+/// A generator that is resumed with a mutable reference instead of an owned value.
+///
+/// The reference is only valid until the generator suspends again. Inside of the generator it can
+/// be accessed with [with_input](struct.BorrowingYielder.html#method.with_input), the closure
+/// passed to it can't hold on to the reference. This allows passing values to a generator that
+/// can't be cloned cheaply or at all, like the `Context` of a future's `poll`.
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use switcheroo::stack::*;
+/// use switcheroo::{BorrowedInput, BorrowingGenerator, GeneratorState};
+///
+/// struct Lines;
+///
+/// impl BorrowedInput for Lines {
+///     type Input<'r> = Vec<&'r str>;
+/// }
+///
+/// let stack = EightMbStack::new().unwrap();
+/// let mut count = BorrowingGenerator::<Lines, _, _, _>::new(stack, |yielder| {
+///     let mut total = 0;
+///     loop {
+///         let lines = yielder.with_input(|lines| lines.drain(..).count());
+///         if lines == 0 {
+///             return total;
+///         }
+///         total += lines;
+///         yielder.suspend(total);
+///     }
+/// })
+/// .unwrap();
+///
+/// let text = String::from("a\nb\nc");
+/// assert_eq!(count.resume(&mut text.lines().collect()), Ok(GeneratorState::Yielded(3)));
+/// assert_eq!(count.resume(&mut vec!["d"]), Ok(GeneratorState::Yielded(4)));
+/// assert_eq!(count.resume(&mut vec![]), Ok(GeneratorState::Complete(4)));
+/// # }
+/// ```
+pub struct BorrowingGenerator<'a, I: BorrowedInput, Yield: 'a, Return: 'a, Stack: stack::Stack> {
+    generator: Generator<'a, NonNull<I::Input<'static>>, Yield, Return, Stack>,
+}
+
+impl<'a, I, Yield, Return, Stack> BorrowingGenerator<'a, I, Yield, Return, Stack>
+where
+    I: BorrowedInput,
+    Yield: 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+{
+    /// Create a new borrowing generator from a stack and closure.
+    ///
+    /// Returns an error if the generator can't use the stack (see
+    /// [Generator::new](struct.Generator.html#method.new)).
+    pub fn new<F>(stack: Stack, f: F) -> Result<Self, Error>
+    where
+        F: FnOnce(&BorrowingYielder<I, Yield>) -> Return + GeneratorSend + 'a,
+    {
+        let generator = Generator::new(stack, |yielder, input| {
+            f(&BorrowingYielder::new(yielder, input))
+        })?;
+        Ok(BorrowingGenerator { generator })
+    }
+
+    /// Create a new borrowing generator that measures how much of the stack the closure uses, see
+    /// [Generator::with_measurement](struct.Generator.html#method.with_measurement).
+    #[cfg(feature = "std")]
+    pub fn with_measurement<F>(
+        stack: Stack,
+        measurement: stack::Measurement,
+        f: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnOnce(&BorrowingYielder<I, Yield>) -> Return + GeneratorSend + 'a,
+    {
+        let generator = Generator::with_measurement(stack, measurement, |yielder, input| {
+            f(&BorrowingYielder::new(yielder, input))
+        })?;
+        Ok(BorrowingGenerator { generator })
+    }
+
+    /// Resume the generator with a reference to `input`, that it can use until it suspends again.
+    ///
+    /// See [Generator::resume](struct.Generator.html#method.resume).
+    #[inline(always)]
+    pub fn resume(
+        &mut self,
+        input: &mut I::Input<'_>,
+    ) -> Result<GeneratorState<Yield, Return>, Fault> {
+        // The lifetime is erased here and only handed out again for the duration of a
+        // `with_input` call, which can't outlast this `resume`.
+        self.generator
+            .resume(NonNull::from(input).cast::<I::Input<'static>>())
+    }
+
+    /// See [Generator::set_stack_limit_slack](struct.Generator.html#method.set_stack_limit_slack).
+    #[cfg(feature = "std")]
+    pub fn set_stack_limit_slack(&mut self, slack: usize) {
+        self.generator.set_stack_limit_slack(slack)
+    }
+
+    /// Returns an id that is unique to this generator for the lifetime of the process.
+    #[inline(always)]
+    pub fn id(&self) -> usize {
+        self.generator.id()
+    }
+
+    /// Returns true if the execution of the passed in closure started
+    #[inline(always)]
+    pub fn started(&self) -> bool {
+        self.generator.started()
+    }
+
+    /// Returns true if the generator finished running.
+    #[inline(always)]
+    pub fn finished(&self) -> bool {
+        self.generator.finished()
+    }
+
+    /// Returns true if the generator was abandoned after a fault.
+    #[inline(always)]
+    pub fn abandoned(&self) -> bool {
+        self.generator.abandoned()
+    }
+
+    /// See [Generator::stack_high_water_mark](struct.Generator.html#method.stack_high_water_mark).
+    #[cfg(feature = "std")]
+    pub fn stack_high_water_mark(&self) -> Option<usize> {
+        self.generator.stack_high_water_mark()
+    }
+
+    /// See [Generator::stack_bytes_resident](struct.Generator.html#method.stack_bytes_resident).
+    #[cfg(feature = "std")]
+    pub fn stack_bytes_resident(&self) -> Result<usize, Error> {
+        self.generator.stack_bytes_resident()
+    }
+
+    /// Consume the generator and extract the stack.
+    pub fn stack(self) -> Stack {
+        self.generator.stack()
+    }
+}
+
+/// BorrowingYielder is the interface provided to every borrowing generator through which it yields
+/// values and accesses the input it was resumed with.
+pub struct BorrowingYielder<'y, I: BorrowedInput, Yield> {
+    yielder: &'y Yielder<NonNull<I::Input<'static>>, Yield>,
+    // `None` while the input is borrowed by `with_input`.
+    input: Cell<Option<NonNull<I::Input<'static>>>>,
+}
+
+impl<'y, I: BorrowedInput, Yield> BorrowingYielder<'y, I, Yield> {
+    fn new(
+        yielder: &'y Yielder<NonNull<I::Input<'static>>, Yield>,
+        input: NonNull<I::Input<'static>>,
+    ) -> Self {
+        BorrowingYielder {
+            yielder,
+            input: Cell::new(Some(input)),
+        }
+    }
+
+    /// Calls `f` with the input of the last `resume`. The reference can't escape the closure, it's
+    /// only valid until the generator suspends.
+    ///
+    /// Panics if it's called from inside of `f`.
+    pub fn with_input<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut I::Input<'_>) -> R,
+    {
+        let mut input = self
+            .input
+            .take()
+            .expect("the resume input is already borrowed");
+        // The caller of `resume` is waiting for the generator to suspend and `f` can't be called
+        // again until it returns.
+        let result = f(unsafe { input.as_mut() });
+        self.input.set(Some(input));
+        result
+    }
+
+    /// Suspends the generator and returns `GeneratorState::Yielded(val)` from the `resume()`
+    /// invocation that resumed the generator. The input of the next `resume` replaces the current
+    /// one.
+    ///
+    /// Panics if it's called from inside of [with_input](#method.with_input).
+    #[inline(always)]
+    pub fn suspend(&self, val: Yield) {
+        assert!(
+            self.input.get().is_some(),
+            "can't suspend while the resume input is borrowed"
+        );
+        let input = self.yielder.suspend(val);
+        self.input.set(Some(input));
+    }
+
+    /// Returns the number of bytes left on the stack the code is currently running on, see
+    /// [Yielder::stack_remaining](struct.Yielder.html#method.stack_remaining).
+    #[cfg(feature = "std")]
+    pub fn stack_remaining(&self) -> Option<usize> {
+        self.yielder.stack_remaining()
+    }
+}
@@ -0,0 +1,85 @@
+use core::iter::FusedIterator;
+
+use crate::{stack, Error, Generator, GeneratorSend};
+
+/// Runs the producer `f` on `stack` and returns an iterator over the items it passes to its
+/// `emit` callback.
+///
+/// The producer only runs while the iterator is pulled, each call to `emit` suspends it until the
+/// next item is requested. This turns APIs that push items through a callback (tree walkers, SAX
+/// parsers, directory scanners, ...) into iterators.
+///
+/// If the iterator is dropped before the producer finishes, the producer's stack is unwound: the
+/// pending `emit` call panics and the panic travels up through the producer's frames. Producers
+/// that call `emit` from a callback invoked by foreign code (e.g. a C library) can't be unwound,
+/// unwinding out of an `extern "C"` function aborts the process and unwinding through C frames is
+/// undefined behavior. Their iterators need to be exhausted before they are dropped.
+///
+/// Returns an error if the generator can't use the stack (see
+/// [Generator::new](struct.Generator.html#method.new)).
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use switcheroo::stack::*;
+///
+/// fn walk(depth: u32, emit: &mut dyn FnMut(u32)) {
+///     emit(depth);
+///     if depth < 3 {
+///         walk(depth + 1, emit);
+///     }
+/// }
+///
+/// let stack = EightMbStack::new().unwrap();
+/// let depths = switcheroo::from_callback(stack, |emit| walk(0, emit)).unwrap();
+/// assert_eq!(depths.collect::<Vec<_>>(), [0, 1, 2, 3]);
+/// # }
+/// ```
+pub fn from_callback<'a, T, Stack, F>(
+    stack: Stack,
+    f: F,
+) -> Result<FromCallback<'a, T, Stack>, Error>
+where
+    T: 'a,
+    Stack: stack::Stack,
+    F: FnOnce(&mut dyn FnMut(T)) + GeneratorSend + 'a,
+{
+    let generator = Generator::new(stack, |yielder, ()| f(&mut |item| yielder.suspend(item)))?;
+    Ok(FromCallback { generator })
+}
+
+/// An iterator over the items a producer emits, see [from_callback](fn.from_callback.html).
+pub struct FromCallback<'a, T: 'a, Stack: stack::Stack> {
+    generator: Generator<'a, (), T, (), Stack>,
+}
+
+impl<'a, T, Stack> FromCallback<'a, T, Stack>
+where
+    T: 'a,
+    Stack: stack::Stack,
+{
+    /// Consume the iterator and extract the stack. If the producer didn't finish its stack is
+    /// unwound first.
+    pub fn stack(self) -> Stack {
+        self.generator.stack()
+    }
+}
+
+impl<'a, T, Stack> Iterator for FromCallback<'a, T, Stack>
+where
+    T: 'a,
+    Stack: stack::Stack,
+{
+    type Item = T;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.generator.next()
+    }
+}
+
+impl<'a, T, Stack> FusedIterator for FromCallback<'a, T, Stack>
+where
+    T: 'a,
+    Stack: stack::Stack,
+{
+}
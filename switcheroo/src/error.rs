@@ -0,0 +1,82 @@
+use alloc::string::String;
+use core::fmt;
+
+/// The error returned when a generator or stack can't be created.
+///
+/// With the `std` feature it converts from and into a `std::io::Error`, errors reported by the OS
+/// keep their kind and error code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    message: String,
+    #[cfg(feature = "std")]
+    kind: std::io::ErrorKind,
+    // The error code, if the error was reported by the OS.
+    #[cfg(feature = "std")]
+    raw_os_error: Option<i32>,
+}
+
+impl Error {
+    /// Creates an error from a message describing it.
+    pub fn new<M: Into<String>>(message: M) -> Self {
+        Error {
+            message: message.into(),
+            #[cfg(feature = "std")]
+            kind: std::io::ErrorKind::Other,
+            #[cfg(feature = "std")]
+            raw_os_error: None,
+        }
+    }
+
+    /// Returns the message describing the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the kind of the error, `ErrorKind::Other` for errors created with
+    /// [new](struct.Error.html#method.new).
+    #[cfg(feature = "std")]
+    pub fn kind(&self) -> std::io::ErrorKind {
+        self.kind
+    }
+
+    /// Returns the error code if the error was reported by the OS.
+    #[cfg(feature = "std")]
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.raw_os_error
+    }
+
+    /// Returns the last error reported by the OS on this thread.
+    #[cfg(feature = "std")]
+    pub(crate) fn last_os_error() -> Self {
+        std::io::Error::last_os_error().into()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error {
+            message: error.to_string(),
+            kind: error.kind(),
+            raw_os_error: error.raw_os_error(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        match error.raw_os_error {
+            Some(code) => std::io::Error::from_raw_os_error(code),
+            None => std::io::Error::new(error.kind, error.message),
+        }
+    }
+}
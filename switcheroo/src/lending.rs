@@ -0,0 +1,284 @@
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+use crate::{stack, Error, Fault, Generator, GeneratorSend, GeneratorState, Yielder};
+
+/// A generator that lends out references into its own stack instead of moving values out.
+///
+/// Each [suspend](struct.LendingYielder.html#method.suspend) passes a `&mut Yield` to the caller
+/// of [resume](struct.LendingGenerator.html#method.resume). The reference stays valid until the
+/// generator is resumed again, this is enforced by borrowing the generator mutably for as long as
+/// the reference is used. Large buffers can be yielded without copying or heap-allocating them.
+///
+/// Generators created with [new_shared](struct.LendingGenerator.html#method.new_shared) lend out
+/// `&Yield` instead, for values the caller should only read.
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use switcheroo::stack::*;
+/// use switcheroo::{GeneratorState, LendingGenerator};
+///
+/// let stack = EightMbStack::new().unwrap();
+/// let mut records = LendingGenerator::new(stack, |yielder, ()| {
+///     let mut record = [0u8; 4096];
+///     for i in 0..3 {
+///         record.fill(i);
+///         yielder.suspend(&mut record[..]);
+///     }
+/// })
+/// .unwrap();
+/// while let Ok(GeneratorState::Yielded(record)) = records.resume(()) {
+///     assert!(record.iter().all(|&byte| byte == record[0]));
+/// }
+/// # }
+/// ```
+///
+/// A reference can't be held on to across `resume` calls:
+///
+/// ```compile_fail
+/// # use switcheroo::stack::*;
+/// # use switcheroo::{GeneratorState, LendingGenerator};
+/// # let stack = EightMbStack::new().unwrap();
+/// let mut generator = LendingGenerator::new(stack, |yielder, ()| {
+///     let mut value = 1;
+///     yielder.suspend(&mut value);
+/// })
+/// .unwrap();
+/// let first = generator.resume(());
+/// let _second = generator.resume(());
+/// drop(first);
+/// ```
+pub struct LendingGenerator<
+    'a,
+    Input: 'a,
+    Yield: ?Sized + 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+    Access = Exclusive,
+> {
+    generator: Generator<'a, Input, NonNull<Yield>, Return, Stack>,
+    access: PhantomData<fn() -> Access>,
+}
+
+/// Marks a [LendingGenerator](struct.LendingGenerator.html) that lends out `&mut Yield`.
+pub enum Exclusive {}
+
+/// Marks a [LendingGenerator](struct.LendingGenerator.html) that lends out `&Yield`.
+pub enum Shared {}
+
+impl<'a, Input, Yield, Return, Stack> LendingGenerator<'a, Input, Yield, Return, Stack, Exclusive>
+where
+    Input: 'a,
+    Yield: ?Sized + 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+{
+    /// Create a new lending generator from a stack and closure.
+    ///
+    /// Returns an error if the generator can't use the stack (see
+    /// [Generator::new](struct.Generator.html#method.new)).
+    pub fn new<F>(stack: Stack, f: F) -> Result<Self, Error>
+    where
+        F: FnOnce(&LendingYielder<Input, Yield>, Input) -> Return + GeneratorSend + 'a,
+    {
+        let generator = Generator::new(stack, |yielder, input| {
+            f(LendingYielder::from_yielder(yielder), input)
+        })?;
+        Ok(LendingGenerator {
+            generator,
+            access: PhantomData,
+        })
+    }
+
+    /// Create a new lending generator that measures how much of the stack the closure uses, see
+    /// [Generator::with_measurement](struct.Generator.html#method.with_measurement).
+    #[cfg(feature = "std")]
+    pub fn with_measurement<F>(
+        stack: Stack,
+        measurement: stack::Measurement,
+        f: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnOnce(&LendingYielder<Input, Yield>, Input) -> Return + GeneratorSend + 'a,
+    {
+        let generator = Generator::with_measurement(stack, measurement, |yielder, input| {
+            f(LendingYielder::from_yielder(yielder), input)
+        })?;
+        Ok(LendingGenerator {
+            generator,
+            access: PhantomData,
+        })
+    }
+
+    /// Resume the generator, returning a reference to the next lent value or the return value of
+    /// the closure once it finishes. The reference is valid until the generator is resumed again.
+    ///
+    /// See [Generator::resume](struct.Generator.html#method.resume).
+    #[inline(always)]
+    pub fn resume(&mut self, input: Input) -> Result<GeneratorState<&mut Yield, Return>, Fault> {
+        match self.generator.resume(input)? {
+            // The value lives on the generator's stack and it stays suspended until the next
+            // `resume`, which needs the mutable borrow the reference is tied to.
+            GeneratorState::Yielded(mut value) => {
+                Ok(GeneratorState::Yielded(unsafe { value.as_mut() }))
+            }
+            GeneratorState::Complete(value) => Ok(GeneratorState::Complete(value)),
+        }
+    }
+}
+
+impl<'a, Input, Yield, Return, Stack> LendingGenerator<'a, Input, Yield, Return, Stack, Shared>
+where
+    Input: 'a,
+    Yield: ?Sized + 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+{
+    /// Create a new lending generator that lends out shared references from a stack and closure.
+    ///
+    /// Returns an error if the generator can't use the stack (see
+    /// [Generator::new](struct.Generator.html#method.new)).
+    pub fn new_shared<F>(stack: Stack, f: F) -> Result<Self, Error>
+    where
+        F: FnOnce(&LendingYielder<Input, Yield, Shared>, Input) -> Return + GeneratorSend + 'a,
+    {
+        let generator = Generator::new(stack, |yielder, input| {
+            f(LendingYielder::from_yielder(yielder), input)
+        })?;
+        Ok(LendingGenerator {
+            generator,
+            access: PhantomData,
+        })
+    }
+
+    /// Like [new_shared](struct.LendingGenerator.html#method.new_shared), but measures how much
+    /// of the stack the closure uses.
+    #[cfg(feature = "std")]
+    pub fn with_measurement_shared<F>(
+        stack: Stack,
+        measurement: stack::Measurement,
+        f: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnOnce(&LendingYielder<Input, Yield, Shared>, Input) -> Return + GeneratorSend + 'a,
+    {
+        let generator = Generator::with_measurement(stack, measurement, |yielder, input| {
+            f(LendingYielder::from_yielder(yielder), input)
+        })?;
+        Ok(LendingGenerator {
+            generator,
+            access: PhantomData,
+        })
+    }
+
+    /// Resume the generator, returning a shared reference to the next lent value or the return
+    /// value of the closure once it finishes. The reference is valid until the generator is
+    /// resumed again.
+    #[inline(always)]
+    pub fn resume(&mut self, input: Input) -> Result<GeneratorState<&Yield, Return>, Fault> {
+        match self.generator.resume(input)? {
+            // Same as the exclusive variant, but the value was lent out through a shared
+            // reference and must only be read.
+            GeneratorState::Yielded(value) => {
+                Ok(GeneratorState::Yielded(unsafe { value.as_ref() }))
+            }
+            GeneratorState::Complete(value) => Ok(GeneratorState::Complete(value)),
+        }
+    }
+}
+
+impl<'a, Input, Yield, Return, Stack, Access>
+    LendingGenerator<'a, Input, Yield, Return, Stack, Access>
+where
+    Input: 'a,
+    Yield: ?Sized + 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+{
+    /// See [Generator::set_stack_limit_slack](struct.Generator.html#method.set_stack_limit_slack).
+    #[cfg(feature = "std")]
+    pub fn set_stack_limit_slack(&mut self, slack: usize) {
+        self.generator.set_stack_limit_slack(slack)
+    }
+
+    /// Returns an id that is unique to this generator for the lifetime of the process.
+    #[inline(always)]
+    pub fn id(&self) -> usize {
+        self.generator.id()
+    }
+
+    /// Returns true if the execution of the passed in closure started
+    #[inline(always)]
+    pub fn started(&self) -> bool {
+        self.generator.started()
+    }
+
+    /// Returns true if the generator finished running.
+    #[inline(always)]
+    pub fn finished(&self) -> bool {
+        self.generator.finished()
+    }
+
+    /// Returns true if the generator was abandoned after a fault.
+    #[inline(always)]
+    pub fn abandoned(&self) -> bool {
+        self.generator.abandoned()
+    }
+
+    /// See [Generator::stack_high_water_mark](struct.Generator.html#method.stack_high_water_mark).
+    #[cfg(feature = "std")]
+    pub fn stack_high_water_mark(&self) -> Option<usize> {
+        self.generator.stack_high_water_mark()
+    }
+
+    /// See [Generator::stack_bytes_resident](struct.Generator.html#method.stack_bytes_resident).
+    #[cfg(feature = "std")]
+    pub fn stack_bytes_resident(&self) -> Result<usize, Error> {
+        self.generator.stack_bytes_resident()
+    }
+
+    /// Consume the generator and extract the stack.
+    pub fn stack(self) -> Stack {
+        self.generator.stack()
+    }
+}
+
+/// LendingYielder is the interface provided to every lending generator through which it lends
+/// out references.
+#[repr(transparent)]
+pub struct LendingYielder<Input, Yield: ?Sized, Access = Exclusive> {
+    yielder: Yielder<Input, NonNull<Yield>>,
+    access: PhantomData<fn() -> Access>,
+}
+
+impl<Input, Yield: ?Sized> LendingYielder<Input, Yield, Exclusive> {
+    /// Suspends the generator and returns `GeneratorState::Yielded(val)` from the `resume()`
+    /// invocation that resumed the generator. `val` is borrowed until the generator is resumed.
+    #[inline(always)]
+    pub fn suspend(&self, val: &mut Yield) -> Input {
+        self.yielder.suspend(NonNull::from(val))
+    }
+}
+
+impl<Input, Yield: ?Sized> LendingYielder<Input, Yield, Shared> {
+    /// Suspends the generator and returns `GeneratorState::Yielded(val)` from the `resume()`
+    /// invocation that resumed the generator. `val` is borrowed until the generator is resumed.
+    #[inline(always)]
+    pub fn suspend(&self, val: &Yield) -> Input {
+        self.yielder.suspend(NonNull::from(val))
+    }
+}
+
+impl<Input, Yield: ?Sized, Access> LendingYielder<Input, Yield, Access> {
+    fn from_yielder(yielder: &Yielder<Input, NonNull<Yield>>) -> &Self {
+        // `LendingYielder` is a transparent wrapper around the yielder.
+        unsafe { &*(yielder as *const Yielder<Input, NonNull<Yield>> as *const Self) }
+    }
+
+    /// Returns the number of bytes left on the stack the code is currently running on, see
+    /// [Yielder::stack_remaining](struct.Yielder.html#method.stack_remaining).
+    #[cfg(feature = "std")]
+    pub fn stack_remaining(&self) -> Option<usize> {
+        self.yielder.stack_remaining()
+    }
+}
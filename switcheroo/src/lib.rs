@@ -1,174 +1,503 @@
-#![feature(naked_functions)]
-
 //! Switcheroo provides lightweight context switches in Rust.
 //!
 //! It consists of two parts:
-//! 1. A stack implementation (currently only providing a [fixed 8Mb stack](stack/struct.EightMbStack.html)).
+//! 1. A stack implementation (a [configurable stack](stack/struct.StackBuilder.html) and presets of it,
+//!    like a [fixed 8Mb stack](stack/struct.EightMbStack.html)).
 //! 2. A [generator](struct.Generator.html) implementation.
 //! ## Example
 //! ```
 //! use switcheroo::stack::*;
-//! use switcheroo::Generator;
+//! use switcheroo::{Generator, GeneratorState};
 //!
-//! fn  main() {
-//! 	let stack = EightMbStack::new().unwrap();
-//! 	let mut add_one = Generator::new(stack, |yielder, mut input| {
-//! 		loop {
-//! 			if input ==  0 {
-//! 				break;
-//! 			}
-//! 			input = yielder.suspend(input +  1);
-//! 		}
-//! 	});
+//! # #[cfg(feature = "std")]
+//! fn main() {
+//!     let stack = EightMbStack::new().unwrap();
+//!     let mut add_one = Generator::new(stack, |yielder, mut input| {
+//!         let mut calls = 0;
+//!         while input != 0 {
+//!             calls += 1;
+//!             input = yielder.suspend(input + 1);
+//!         }
+//!         calls
+//!     })
+//!     .unwrap();
 //!
-//! 	assert_eq!(add_one.resume(2), Some(3));
-//! 	assert_eq!(add_one.resume(127), Some(128));
-//! 	assert_eq!(add_one.resume(0), None);
-//! 	assert_eq!(add_one.resume(0), None);
+//!     assert_eq!(add_one.resume(2), Ok(GeneratorState::Yielded(3)));
+//!     assert_eq!(add_one.resume(127), Ok(GeneratorState::Yielded(128)));
+//!     assert_eq!(add_one.resume(0), Ok(GeneratorState::Complete(2)));
 //! }
-// ```
+//! # #[cfg(not(feature = "std"))]
+//! # fn main() {}
+//! ```
+//!
+//! ## `no_std`
+//!
+//! Without the default `std` feature switcheroo only depends on `core` and `alloc`. Generators
+//! can then only run on [user-provided stacks](stack/trait.Stack.html) and panics inside of them
+//! need to abort the process (`panic = "abort"`), they can't unwind across the context switch.
+//! Faults are not recovered from and the stack of a generator can't be inspected.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "nightly", feature(coroutine_trait))]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod active;
 mod arch;
+mod borrowing;
+mod callback;
+mod error;
+mod lending;
+#[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+mod signal;
 pub mod stack;
 
+#[cfg(feature = "std")]
+pub use active::{maybe_grow, remaining_stack, stack_limit, stack_limit_ptr};
+pub use borrowing::{BorrowedInput, BorrowingGenerator, BorrowingYielder};
+pub use callback::{from_callback, FromCallback};
+pub use error::Error;
+pub use lending::{Exclusive, LendingGenerator, LendingYielder, Shared};
+
+use alloc::format;
+use core::cell::Cell;
+use core::fmt;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+#[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+use core::ops::Range;
+#[cfg(feature = "nightly")]
+use core::ops::{Coroutine, CoroutineState};
+#[cfg(feature = "nightly")]
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    mem,
+    ptr::{self, NonNull},
+};
+#[cfg(feature = "std")]
 use std::any::Any;
-use std::cell::Cell;
-use std::marker::PhantomData;
+#[cfg(feature = "std")]
 use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
-use std::{mem, ptr::NonNull};
-
-// Communicates the return of the Generator.
-enum GeneratorOutput<Output> {
-    // The generator returned a regular value.
-    Value(Output),
-    // The generator finished and there are no more values to be returned.
-    Finished,
-    // The generator panicked. This value is passed to `resume_unwind` to continue the unwind
+
+// Source of the generator ids.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+// The Err part of std::thread::Result. Without std panics abort and there is never one.
+#[cfg(feature = "std")]
+type Panic = Box<dyn Any + Send + 'static>;
+#[cfg(not(feature = "std"))]
+type Panic = core::convert::Infallible;
+
+// Communicates the return of the Generator. Only a pointer to it is passed across the context
+// switch, `value` points to a value of the type that `kind` describes. The yielder doesn't know the
+// return type of the generator, so the values can't be part of one enum.
+struct GeneratorOutput {
+    kind: OutputKind,
+    value: *const (),
+}
+
+enum OutputKind {
+    // The generator suspended with a value.
+    Yielded,
+    // The generator finished and returned a value.
+    Complete,
+    // The generator panicked. The value is passed to `resume_unwind` to continue the unwind
     // across contexts.
-    Panic(Box<dyn Any + Send + 'static>), // Err part of std::thread::Result
+    #[cfg(feature = "std")]
+    Panic,
+}
+
+impl GeneratorOutput {
+    fn new<T>(kind: OutputKind, value: &T) -> Self {
+        GeneratorOutput {
+            kind,
+            value: value as *const T as *const (),
+        }
+    }
+
+    // Moves the value out. `Yield` and `Return` need to be the types of the generator that sent it
+    // and the value can only be read once.
+    unsafe fn read<Yield, Return>(&self) -> Result<GeneratorState<Yield, Return>, Panic> {
+        match self.kind {
+            OutputKind::Yielded => Ok(GeneratorState::Yielded(ptr::read(
+                self.value as *const Yield,
+            ))),
+            OutputKind::Complete => Ok(GeneratorState::Complete(ptr::read(
+                self.value as *const Return,
+            ))),
+            #[cfg(feature = "std")]
+            OutputKind::Panic => Err(ptr::read(self.value as *const Panic)),
+        }
+    }
 }
 
-/// Generator wraps a closure and allows suspending its execution more than once, returning
+/// The result of [resuming](struct.Generator.html#method.resume) a generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GeneratorState<Yield, Return> {
+    /// The generator suspended with a value.
+    Yielded(Yield),
+    /// The generator finished and returned a value.
+    Complete(Return),
+}
+
+/// Implemented by the closures a generator accepts.
+///
+/// With the `thread` backend each generator runs on its own thread, so only closures that are
+/// `Send` are accepted. Otherwise this is implemented for all types.
+#[cfg(feature = "thread")]
+pub trait GeneratorSend: Send {}
+
+#[cfg(feature = "thread")]
+impl<T: Send + ?Sized> GeneratorSend for T {}
+
+/// Implemented by the closures a generator accepts.
+///
+/// With the `thread` backend each generator runs on its own thread, so only closures that are
+/// `Send` are accepted. Otherwise this is implemented for all types.
+#[cfg(not(feature = "thread"))]
+pub trait GeneratorSend {}
+
+#[cfg(not(feature = "thread"))]
+impl<T: ?Sized> GeneratorSend for T {}
+
+/// Generator wraps a closure and allows suspending its execution more than once, yielding
 /// a value each time.
 ///
-/// If the closure finishes each other call to [resume](struct.Generator.html#method.resume)
-/// will yield `None`. If the closure panics the unwind will happen correctly across contexts.
-pub struct Generator<'a, Input: 'a, Output: 'a, Stack: stack::Stack> {
+/// Once the closure finishes, [resume](struct.Generator.html#method.resume) returns its return
+/// value as [GeneratorState::Complete](enum.GeneratorState.html#variant.Complete). Resuming it
+/// again panics. If the closure panics the unwind will happen correctly across contexts.
+///
+/// If the closure faults (e.g. overflows the stack into its guard region or triggers a SIGSEGV)
+/// [resume](struct.Generator.html#method.resume) returns a [Fault](struct.Fault.html) and the
+/// generator is abandoned. This is only supported on Linux, on other Unix platforms the process is
+/// aborted with a message containing the generator's [id](struct.Generator.html#method.id) if the
+/// stack overflowed.
+///
+/// Without the `std` feature panics inside of the closure abort the process and dropping a
+/// generator that didn't finish doesn't unwind its stack, the values living on it are leaked.
+pub struct Generator<'a, Input: 'a, Yield: 'a, Return: 'a, Stack: stack::Stack> {
+    id: usize,
+    #[cfg(feature = "std")]
+    active: active::Active,
+    #[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+    guard: Range<usize>,
+    // The closure waiting in the stack image until the first `resume` links the stacks.
+    entry: Option<Entry>,
     started: bool,
+    abandoned: bool,
+    #[cfg(feature = "std")]
+    measurement: Option<stack::Measurement>,
     stack: Option<Stack>,
     stack_ptr: Option<NonNull<usize>>,
-    phantom: PhantomData<(&'a (), *mut Input, *const Output)>,
+    // The context of the generator's thread, it exits once the generator is dropped.
+    #[cfg(feature = "thread")]
+    context: *mut usize,
+    phantom: PhantomData<(&'a (), *mut Input, *const Yield, *const Return)>,
 }
 
-unsafe impl<'a, Input, Output, Stack> Send for Generator<'a, Input, Output, Stack>
+// A closure that was moved into the stack image of a generator. The initial frame is set up right
+// below it.
+struct Entry {
+    f_ptr: *mut usize,
+    // Drops the closure in place if the generator is dropped before it's resumed.
+    drop: unsafe fn(*mut usize),
+}
+
+unsafe impl<'a, Input, Yield, Return, Stack> Send for Generator<'a, Input, Yield, Return, Stack>
 where
     Input: 'a,
-    Output: 'a,
+    Yield: 'a,
+    Return: 'a,
     Stack: stack::Stack,
 {
 }
 
-impl<'a, Input, Output, Stack> Generator<'a, Input, Output, Stack>
+impl<'a, Input, Yield, Return, Stack> Generator<'a, Input, Yield, Return, Stack>
 where
     Input: 'a,
-    Output: 'a,
+    Yield: 'a,
+    Return: 'a,
     Stack: stack::Stack,
 {
     /// Create a new generator from a stack and closure.
-    pub fn new<F>(stack: Stack, f: F) -> Generator<'a, Input, Output, Stack>
+    ///
+    /// Returns an error if the bottom of the stack is not aligned to
+    /// [STACK_ALIGNMENT](stack/constant.STACK_ALIGNMENT.html) or if the stack is smaller than
+    /// [MIN_STACK_SIZE](stack/constant.MIN_STACK_SIZE.html).
+    pub fn new<F>(stack: Stack, f: F) -> Result<Generator<'a, Input, Yield, Return, Stack>, Error>
+    where
+        F: FnOnce(&Yielder<Input, Yield>, Input) -> Return + GeneratorSend + 'a,
+    {
+        Generator::create(stack, f)
+    }
+
+    /// Create a new generator that measures how much of the stack the closure uses, see
+    /// [stack_high_water_mark](struct.Generator.html#method.stack_high_water_mark).
+    #[cfg(feature = "std")]
+    pub fn with_measurement<F>(
+        stack: Stack,
+        measurement: stack::Measurement,
+        f: F,
+    ) -> Result<Generator<'a, Input, Yield, Return, Stack>, Error>
+    where
+        F: FnOnce(&Yielder<Input, Yield>, Input) -> Return + GeneratorSend + 'a,
+    {
+        // The stack needs to be prepared before the generator sets up its initial frame on it.
+        stack::validate(&stack)?;
+        stack::measure::prepare(&stack, measurement);
+        let mut generator = Generator::create(stack, f)?;
+        generator.measurement = Some(measurement);
+        Ok(generator)
+    }
+
+    fn create<F>(stack: Stack, f: F) -> Result<Generator<'a, Input, Yield, Return, Stack>, Error>
     where
-        F: FnOnce(&Yielder<Input, Output>, Input) + 'a,
+        F: FnOnce(&Yielder<Input, Yield>, Input) -> Return + GeneratorSend + 'a,
     {
         // This function will be written to the new stack (by `arch::init`) as the initial
-        // entry point. During the `arch::swap_and_link_stacks` call it will be called with
-        // the correct closure passed as the first argument. This function will never return.
-        // Yielding back into it after `yielder.suspend_(GeneratorOutput::Finished)` was
-        // called would be undefined behavior.
-        unsafe extern "C" fn generator_wrapper<Input, Output, Stack, F>(
+        // entry point. During the `arch::swap_and_link_stacks` call in the first `resume` it will
+        // be called with the closure in the stack image passed as the first argument. This
+        // function will never return, except on the `thread` backend. Yielding back into it after
+        // it reported that it finished would be undefined behavior on all other backends.
+        unsafe extern "C" fn generator_wrapper<Input, Yield, Return, F>(
             f_ptr: usize,
             stack_ptr: *mut usize,
         ) where
-            Stack: stack::Stack,
-            F: FnOnce(&Yielder<Input, Output>, Input),
+            F: FnOnce(&Yielder<Input, Yield>, Input) -> Return,
         {
-            let f = std::ptr::read(f_ptr as *const F);
-            let (data, stack_ptr) = arch::swap(0, stack_ptr);
-            let input = std::ptr::read(data as *const Input);
+            // Only the `thread` backend enters generators that were never resumed, with a 0
+            // `f_ptr`, to let their thread exit.
+            if f_ptr == 0 {
+                return;
+            }
+            let f = ptr::read(f_ptr as *const F);
             let yielder = Yielder::new(stack_ptr);
+            let (data, stack_ptr) = arch::swap(0, stack_ptr);
+            yielder.resumed_from(stack_ptr);
+            let input = ptr::read(data as *const Input);
 
             // It is not safe to unwind across the context switch.
             // The unwind will continue in the original context.
-            match catch_unwind(AssertUnwindSafe(|| {
-                f(&yielder, input);
-            })) {
-                Ok(_) => yielder.suspend_(GeneratorOutput::Finished),
-                Err(panic) => yielder.suspend_(GeneratorOutput::Panic(panic)),
+            #[cfg(feature = "std")]
+            let result = catch_unwind(AssertUnwindSafe(|| f(&yielder, input)));
+            // Without std panics abort, there is nothing to catch.
+            #[cfg(not(feature = "std"))]
+            let result: Result<Return, Panic> = Ok(f(&yielder, input));
+            // The value is moved out by `resume`.
+            let result = mem::ManuallyDrop::new(result);
+            let output = match &*result {
+                Ok(value) => GeneratorOutput::new(OutputKind::Complete, value),
+                #[cfg(feature = "std")]
+                Err(panic) => GeneratorOutput::new(OutputKind::Panic, panic),
+                #[cfg(not(feature = "std"))]
+                Err(never) => match *never {},
             };
+            // Only the `thread` backend switches back after this, to let the thread exit.
+            arch::swap(
+                &output as *const GeneratorOutput as usize,
+                yielder.stack_ptr.get(),
+            );
         }
 
-        // Prepare the stack
-        let stack_ptr = unsafe { arch::init(&stack, generator_wrapper::<Input, Output, Stack, F>) };
+        unsafe fn drop_entry<F>(f_ptr: *mut usize) {
+            ptr::drop_in_place(f_ptr as *mut F);
+        }
+
+        stack::validate(&stack)?;
 
-        // f needs to live on after this function, it is part of the new context. This prevents it
-        // from being dropped. The drop happens inside of the `generator_wrapper()` function.
-        let f = mem::ManuallyDrop::new(f);
+        #[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+        signal::init();
 
-        // This call will link the stacks together with assembly directives magic, but once the
-        // first `arch::swap` inside `generator_wrapper` is reached it will yield back before the
-        // execution of the closure `f`.
-        // Only the next call to `resume` will start executing the closure.
+        // The closure is moved to the bottom of the stack, where it waits for the first `resume`.
+        // Nothing is switched to before that, so creating and dropping a generator that never runs
+        // is cheap.
+        let align = mem::align_of::<F>().max(stack::STACK_ALIGNMENT);
+        let f_ptr = match (stack.bottom() as usize).checked_sub(mem::size_of::<F>()) {
+            Some(f_ptr) if f_ptr & !(align - 1) >= stack.top() as usize + stack::MIN_STACK_SIZE => {
+                (f_ptr & !(align - 1)) as *mut usize
+            }
+            _ => {
+                return Err(stack::invalid_input(format!(
+                    "the closure doesn't fit on the stack, it needs at least {} bytes",
+                    mem::size_of::<F>() + stack::MIN_STACK_SIZE
+                )))
+            }
+        };
+        // Prepare the stack
         let stack_ptr = unsafe {
-            arch::swap_and_link_stacks(
-                &f as *const mem::ManuallyDrop<F> as usize,
-                stack_ptr,
-                stack.bottom(),
-            )
-            .1
+            ptr::write(f_ptr as *mut F, f);
+            arch::init(&stack, f_ptr, generator_wrapper::<Input, Yield, Return, F>)
+        };
+        // Only the `thread` backend can fail here, if it can't spawn the generator's thread.
+        #[cfg(feature = "thread")]
+        let stack_ptr = match stack_ptr {
+            Ok(stack_ptr) => stack_ptr,
+            Err(error) => {
+                unsafe { drop_entry::<F>(f_ptr) };
+                return Err(error);
+            }
         };
 
-        Generator {
+        #[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+        let guard = {
+            let top = stack.top() as usize;
+            top - stack.guard_size()..top
+        };
+
+        Ok(Generator {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            #[cfg(feature = "std")]
+            active: active::Active {
+                top: stack.top() as usize,
+                limit: stack.top() as usize,
+            },
+            #[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+            guard,
+            entry: Some(Entry {
+                f_ptr,
+                drop: drop_entry::<F>,
+            }),
             started: false,
+            abandoned: false,
+            #[cfg(feature = "std")]
+            measurement: None,
             stack: Some(stack),
             stack_ptr: Some(NonNull::new(stack_ptr).unwrap()),
+            #[cfg(feature = "thread")]
+            context: stack_ptr,
             phantom: PhantomData,
-        }
+        })
     }
 
-    /// Resume the generator yielding the next value.
+    /// Resume the generator, returning the next yielded value or the return value of the closure
+    /// once it finishes.
+    ///
+    /// Returns an error if the generator faulted while running. The generator is abandoned in this
+    /// case.
+    ///
+    /// Panics if the generator already finished or was abandoned.
     #[inline(always)]
-    pub fn resume(&mut self, input: Input) -> Option<Output> {
-        if self.stack_ptr.is_none() {
-            return None;
+    pub fn resume(&mut self, input: Input) -> Result<GeneratorState<Yield, Return>, Fault> {
+        let stack_ptr = match self.stack_ptr {
+            Some(stack_ptr) => stack_ptr,
+            None if self.abandoned => panic!("generator resumed after a fault"),
+            None => panic!("generator resumed after completion"),
+        };
+        let stack_ptr = match self.entry.take() {
+            Some(entry) => match unsafe { self.link(entry, stack_ptr) } {
+                Ok(stack_ptr) => stack_ptr,
+                Err(fault) => return Err(self.abandon(fault)),
+            },
+            None => stack_ptr,
         };
-        let stack_ptr = self.stack_ptr.unwrap();
 
         unsafe {
             let input = mem::ManuallyDrop::new(input);
             // Mark the `Generator` as started
             self.started = true;
-            let (data_out, stack_ptr) = arch::swap(
-                &input as *const mem::ManuallyDrop<Input> as usize,
-                stack_ptr.as_ptr(),
-            );
+            let arg = &input as *const mem::ManuallyDrop<Input> as usize;
+            let (data_out, stack_ptr) = match self.switch(|| arch::swap(arg, stack_ptr.as_ptr())) {
+                Ok(result) => result,
+                Err(fault) => return Err(self.abandon(fault)),
+            };
 
-            let output = std::ptr::read(data_out as *const GeneratorOutput<Output>);
-            match output {
-                GeneratorOutput::Value(value) => {
+            let output = &*(data_out as *const GeneratorOutput);
+            match output.read() {
+                Ok(GeneratorState::Yielded(value)) => {
                     self.stack_ptr = Some(NonNull::new(stack_ptr).unwrap());
-                    Some(value)
+                    Ok(GeneratorState::Yielded(value))
                 }
-                GeneratorOutput::Finished => {
+                Ok(complete) => {
                     self.stack_ptr = None;
-                    None
+                    Ok(complete)
                 }
-                GeneratorOutput::Panic(panic) => {
+                #[cfg(feature = "std")]
+                Err(panic) => {
                     self.stack_ptr = None;
                     resume_unwind(panic);
                 }
+                #[cfg(not(feature = "std"))]
+                Err(never) => match never {},
             }
         }
     }
 
+    /// Links the stacks together with assembly directives magic, but once the first `arch::swap`
+    /// inside `generator_wrapper` is reached it will yield back before the execution of the
+    /// closure. Like every other switch it goes through `switch`, so a fault in the entry code is
+    /// recovered from. Returns the stack pointer to resume the generator with.
+    #[inline(never)]
+    unsafe fn link(
+        &mut self,
+        entry: Entry,
+        stack_ptr: NonNull<usize>,
+    ) -> Result<NonNull<usize>, Fault> {
+        let (_, stack_ptr) = self.switch(|| {
+            arch::swap_and_link_stacks(entry.f_ptr as usize, stack_ptr.as_ptr(), entry.f_ptr)
+        })?;
+        Ok(NonNull::new(stack_ptr).unwrap())
+    }
+
+    // Nothing on the stack can be trusted anymore after a fault, just forget about it.
+    fn abandon(&mut self, fault: Fault) -> Fault {
+        self.stack_ptr = None;
+        self.abandoned = true;
+        fault
+    }
+
+    /// Switches to the generator's stack with `swap`. While the generator runs it's registered
+    /// with the fault handler.
+    #[inline(always)]
+    unsafe fn switch<F>(&self, swap: F) -> Result<(usize, *mut usize), Fault>
+    where
+        F: FnOnce() -> (usize, *mut usize),
+    {
+        #[cfg(feature = "std")]
+        let previous = active::enter(self.active);
+        #[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+        let result = {
+            let mut running = signal::Running::new(self.id, self.guard.clone());
+            signal::enter(&mut running);
+            let result = swap();
+            signal::leave(&running);
+            match running.fault() {
+                None => Ok(result),
+                Some(fault) => Err(fault),
+            }
+        };
+        #[cfg(any(not(target_family = "unix"), not(feature = "std"), feature = "thread"))]
+        let result = Ok(swap());
+        #[cfg(feature = "std")]
+        active::leave(previous);
+        result
+    }
+
+    /// Resumes the generator like [resume](struct.Generator.html#method.resume), but panics if
+    /// it faults.
+    fn resume_or_panic(&mut self, input: Input) -> GeneratorState<Yield, Return> {
+        match self.resume(input) {
+            Ok(state) => state,
+            Err(fault) => panic!("{}", fault),
+        }
+    }
+
+    /// Sets how many bytes above the top of the stack the [stack limit](fn.stack_limit.html) of this
+    /// generator is. Defaults to 0. This leaves room for code that doesn't check the limit, e.g.
+    /// host functions called by JIT compiled code.
+    #[cfg(feature = "std")]
+    pub fn set_stack_limit_slack(&mut self, slack: usize) {
+        self.active.limit = self.active.top + slack;
+    }
+
+    /// Returns an id that is unique to this generator for the lifetime of the process.
+    /// Stack overflow reports use it to name the generator that overflowed.
+    #[inline(always)]
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
     /// Returns true if the execution of the passed in closure started
     #[inline(always)]
     pub fn started(&self) -> bool {
@@ -181,6 +510,35 @@ where
         self.stack_ptr.is_none()
     }
 
+    /// Returns true if the generator was abandoned after a fault. An abandoned generator is also
+    /// finished, but the values that lived on its stack were never dropped.
+    #[inline(always)]
+    pub fn abandoned(&self) -> bool {
+        self.abandoned
+    }
+
+    /// Returns the number of bytes at the bottom of the stack used so far, including the frames set
+    /// up by the generator itself. Returns `None` if the generator was not created
+    /// [with a measurement](struct.Generator.html#method.with_measurement) or the residency of the
+    /// stack can't be checked.
+    ///
+    /// The value is exact up to a word for [painted](stack/enum.Measurement.html#variant.Paint)
+    /// stacks and up to a page if [residency](stack/enum.Measurement.html#variant.Residency) is
+    /// checked.
+    #[cfg(feature = "std")]
+    pub fn stack_high_water_mark(&self) -> Option<usize> {
+        let stack = self.stack.as_ref().unwrap();
+        self.measurement
+            .and_then(|measurement| stack::measure::high_water_mark(stack, measurement))
+    }
+
+    /// Returns the number of bytes of the stack that are currently backed by physical memory.
+    /// This works for all generators, also the ones created without a measurement.
+    #[cfg(feature = "std")]
+    pub fn stack_bytes_resident(&self) -> Result<usize, Error> {
+        stack::measure::bytes_resident(self.stack.as_ref().unwrap())
+    }
+
     /// Consume the generator and extract the stack.
     pub fn stack(mut self) -> Stack {
         self.stack.take().unwrap()
@@ -188,62 +546,239 @@ where
     }
 }
 
-impl<'a, Input, Output, Stack> Drop for Generator<'a, Input, Output, Stack>
+impl<'a, Input, Yield, Return, Stack> Drop for Generator<'a, Input, Yield, Return, Stack>
 where
     Input: 'a,
-    Output: 'a,
+    Yield: 'a,
+    Return: 'a,
     Stack: stack::Stack,
 {
     fn drop(&mut self) {
-        // If there is still data on the stack unwind it.
+        // A generator that was never resumed only needs to drop its closure.
+        if let Some(entry) = self.entry.take() {
+            unsafe { (entry.drop)(entry.f_ptr) };
+        }
+        // If there is still data on the stack unwind it. Without std there is no unwinding, the
+        // stack is left as it is.
+        #[cfg(feature = "std")]
         if self.started() && !self.finished() {
             unsafe {
-                let (data, _stack_ptr) = arch::swap(0, self.stack_ptr.unwrap().as_ptr());
-                // We catch the unwind in the other context, but don't resume it here (just drop the panic value).
-                let _panic = std::ptr::read(data as *const GeneratorOutput<Output>);
+                // If the unwinding faults there is nothing left to clean up.
+                let stack_ptr = self.stack_ptr.unwrap().as_ptr();
+                if let Ok((data, _stack_ptr)) = self.switch(|| arch::swap(0, stack_ptr)) {
+                    // We catch the unwind in the other context, but don't resume it here (just drop the panic value).
+                    let output = &*(data as *const GeneratorOutput);
+                    let _panic = output.read::<Yield, Return>();
+                }
             };
         }
+        #[cfg(feature = "thread")]
+        unsafe {
+            arch::exit(self.context)
+        };
+    }
+}
+
+// The closure and the values it keeps live on the stack of the generator, moving the generator itself
+// doesn't invalidate them.
+impl<'a, Input, Yield, Return, Stack> Unpin for Generator<'a, Input, Yield, Return, Stack>
+where
+    Input: 'a,
+    Yield: 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+{
+}
+
+/// Generators can be used wherever std's coroutines are accepted. Resuming a generator that faults
+/// panics.
+#[cfg(feature = "nightly")]
+impl<'a, Input, Yield, Return, Stack> Coroutine<Input>
+    for Generator<'a, Input, Yield, Return, Stack>
+where
+    Input: 'a,
+    Yield: 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+{
+    type Yield = Yield;
+    type Return = Return;
+
+    fn resume(self: Pin<&mut Self>, input: Input) -> CoroutineState<Yield, Return> {
+        match self.get_mut().resume_or_panic(input) {
+            GeneratorState::Yielded(value) => CoroutineState::Yielded(value),
+            GeneratorState::Complete(value) => CoroutineState::Complete(value),
+        }
+    }
+}
+
+/// Generators without an input iterate over the values they yield. The value returned by the
+/// closure is dropped. Iterating over a generator that faults panics.
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use switcheroo::stack::*;
+/// use switcheroo::Generator;
+///
+/// let stack = EightMbStack::new().unwrap();
+/// let squares = Generator::new(stack, |yielder, ()| {
+///     for i in 1..4 {
+///         yielder.suspend(i * i);
+///     }
+/// })
+/// .unwrap();
+/// assert_eq!(squares.collect::<Vec<_>>(), [1, 4, 9]);
+/// # }
+/// ```
+impl<'a, Yield, Return, Stack> Iterator for Generator<'a, (), Yield, Return, Stack>
+where
+    Yield: 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+{
+    type Item = Yield;
+
+    fn next(&mut self) -> Option<Yield> {
+        if self.finished() {
+            return None;
+        }
+        match self.resume_or_panic(()) {
+            GeneratorState::Yielded(value) => Some(value),
+            GeneratorState::Complete(_) => None,
+        }
+    }
+}
+
+impl<'a, Yield, Return, Stack> FusedIterator for Generator<'a, (), Yield, Return, Stack>
+where
+    Yield: 'a,
+    Return: 'a,
+    Stack: stack::Stack,
+{
+}
+
+/// A fault that interrupted the execution of a generator.
+///
+/// It's created from the signal (SIGSEGV, SIGBUS, SIGILL or SIGFPE) raised by the code running
+/// inside the generator. Overflowing the stack into its guard region is also reported as a fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    signal: i32,
+    address: usize,
+    stack_overflow: bool,
+}
+
+impl Fault {
+    #[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+    pub(crate) fn new(signal: i32, address: usize, stack_overflow: bool) -> Self {
+        Fault {
+            signal,
+            address,
+            stack_overflow,
+        }
+    }
+
+    /// Returns the number of the signal that was raised.
+    pub fn signal(&self) -> i32 {
+        self.signal
+    }
+
+    /// Returns the faulting address, or the address of the faulting instruction for SIGILL and SIGFPE.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Returns true if the fault happened inside the guard region of the generator's stack.
+    pub fn is_stack_overflow(&self) -> bool {
+        self.stack_overflow
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+        let signal = signal::name(self.signal);
+        #[cfg(any(not(target_family = "unix"), not(feature = "std"), feature = "thread"))]
+        let signal = "signal";
+        if self.stack_overflow {
+            write!(
+                f,
+                "generator stack overflow ({} at {:#x})",
+                signal, self.address
+            )
+        } else {
+            write!(f, "generator faulted ({} at {:#x})", signal, self.address)
+        }
     }
 }
 
-/// Yielder is an interface provided to every generator through which it returns a value.
-pub struct Yielder<Input, Output> {
+impl core::error::Error for Fault {}
+
+/// Yielder is an interface provided to every generator through which it yields values.
+pub struct Yielder<Input, Yield> {
     stack_ptr: Cell<*mut usize>,
-    phantom: PhantomData<(*const Input, *mut Output)>,
+    phantom: PhantomData<(*const Input, *mut Yield)>,
 }
 
-impl<Input, Output> Yielder<Input, Output> {
-    fn new(stack_ptr: *mut usize) -> Yielder<Input, Output> {
-        Yielder {
+impl<Input, Yield> Yielder<Input, Yield> {
+    fn new(stack_ptr: *mut usize) -> Yielder<Input, Yield> {
+        let yielder = Yielder {
             stack_ptr: Cell::new(stack_ptr),
             phantom: PhantomData,
-        }
+        };
+        yielder.resumed_from(stack_ptr);
+        yielder
     }
 
-    /// Suspends the generator and returns `Some(val)` from the `resume()` invocation that resumed
-    /// the generator.
+    // Remembers where to switch back to once the generator yields. The fault handler gets its own
+    // copy of it, outside of the generator's stack.
     #[inline(always)]
-    pub fn suspend(&self, val: Output) -> Input {
-        unsafe { self.suspend_(GeneratorOutput::Value(val)) }
+    fn resumed_from(&self, stack_ptr: *mut usize) {
+        self.stack_ptr.set(stack_ptr);
+        #[cfg(all(target_family = "unix", feature = "std", not(feature = "thread")))]
+        signal::resumed_from(stack_ptr);
+    }
+
+    /// Returns the number of bytes left on the stack the code is currently running on, see
+    /// [remaining_stack](fn.remaining_stack.html). It's `None` with the `thread` backend.
+    #[cfg(feature = "std")]
+    pub fn stack_remaining(&self) -> Option<usize> {
+        active::remaining_stack()
+    }
+
+    /// Returns the [stack limit](fn.stack_limit.html) of the stack the code is currently running
+    /// on. It's `None` with the `thread` backend.
+    #[cfg(feature = "std")]
+    pub fn stack_limit(&self) -> Option<usize> {
+        active::stack_limit()
     }
 
+    /// Suspends the generator and returns `GeneratorState::Yielded(val)` from the `resume()`
+    /// invocation that resumed the generator.
     #[inline(always)]
-    unsafe fn suspend_(&self, out: GeneratorOutput<Output>) -> Input {
-        let out = mem::ManuallyDrop::new(out);
+    pub fn suspend(&self, val: Yield) -> Input {
+        // The value is moved out by `resume`.
+        let val = mem::ManuallyDrop::new(val);
+        unsafe { self.suspend_(GeneratorOutput::new(OutputKind::Yielded, &*val)) }
+    }
+
+    #[inline(always)]
+    unsafe fn suspend_(&self, out: GeneratorOutput) -> Input {
         let (data, stack_ptr) = arch::swap(
-            &out as *const mem::ManuallyDrop<GeneratorOutput<Output>> as usize,
+            &out as *const GeneratorOutput as usize,
             self.stack_ptr.get(),
         );
 
         // Set return point. This needs to happen before unwind is triggered.
-        self.stack_ptr.set(stack_ptr);
+        self.resumed_from(stack_ptr);
 
         // We use the data pointer to signalize an unwind trigger.
         // It should never be 0 otherwise.
+        #[cfg(feature = "std")]
         if data == 0 {
             resume_unwind(Box::new(()));
         }
 
-        std::ptr::read(data as *const Input)
+        ptr::read(data as *const Input)
     }
 }
@@ -0,0 +1,354 @@
+// Fault handling for generators on Unix.
+//
+// Code running inside a generator can fault: it can overflow the stack into the guard region (see
+// `Stack::guard_size`) or, in case of JIT generated code, deliberately trigger a SIGSEGV, SIGBUS,
+// SIGILL or SIGFPE to implement traps. Without any help this would just kill the whole process, so
+// switcheroo installs its own handler for these signals.
+//
+// Before switching to a generator's stack the generator is registered as running on the current
+// thread (`enter`) and unregistered once the execution comes back (`leave`). The registrations form
+// a linked list through the stack frames of the `resume` calls, nested generators just add another
+// element to it. If a signal arrives while a generator is running, the handler records the fault
+// and rewrites the interrupted context (`arch::return_to`), so that once the handler returns the
+// execution continues inside `resume`, like the generator just yielded. The generator's stack is
+// abandoned without unwinding it, it could be in any state at this point.
+//
+// Rewriting the context is only implemented on Linux, the `ucontext` backend instead jumps out of
+// the handler with `setcontext`. On other platforms the handler prints a message naming the
+// generator and aborts the process if its stack overflowed. All faults that didn't happen inside a
+// generator are forwarded to the previously installed handler (e.g. the one the Rust std library
+// uses to detect main thread stack overflows).
+//
+// The handler runs on a separate signal stack (sigaltstack), because by the time it's invoked the
+// generator's stack could already be exhausted. The Rust std library sets one up for each thread it
+// spawns, for all other threads one is allocated the first time they resume a generator.
+
+use std::cell::Cell;
+use std::io::Error;
+use std::mem;
+use std::ops::Range;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Once;
+
+use libc::{
+    c_int, c_void, sigaction, siginfo_t, SA_ONSTACK, SA_SIGINFO, SIGBUS, SIGFPE, SIGILL, SIGSEGV,
+};
+
+use crate::Fault;
+
+/// A generator that is currently executing on this thread.
+pub(crate) struct Running {
+    id: usize,
+    guard: Range<usize>,
+    // The stack pointer of the context that resumed the generator. It's written by the generator
+    // right after the switch, but lives here on the caller's stack, where a misbehaving generator
+    // can't easily overwrite it.
+    caller: Cell<*mut usize>,
+    fault: Cell<Option<Fault>>,
+    parent: *const Running,
+}
+
+impl Running {
+    pub(crate) fn new(id: usize, guard: Range<usize>) -> Self {
+        Running {
+            id,
+            guard,
+            caller: Cell::new(ptr::null_mut()),
+            fault: Cell::new(None),
+            parent: ptr::null(),
+        }
+    }
+
+    /// Returns the fault that interrupted the generator, if there was one.
+    pub(crate) fn fault(&self) -> Option<Fault> {
+        self.fault.get()
+    }
+}
+
+thread_local!(
+    // The innermost generator running on this thread.
+    static CURRENT: Cell<*const Running> = const { Cell::new(ptr::null()) };
+    // Is there a signal stack set up for this thread?
+    static HAS_ALT_STACK: Cell<bool> = const { Cell::new(false) };
+    // A signal stack allocated by switcheroo, it's freed once the thread exits.
+    static ALT_STACK: Cell<Option<AltStack>> = const { Cell::new(None) };
+);
+
+const SIGNALS: [c_int; 4] = [SIGSEGV, SIGBUS, SIGILL, SIGFPE];
+// The handlers installed before ours, in the same order as `SIGNALS`.
+static PREVIOUS: [AtomicPtr<sigaction>; 4] = [
+    AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()),
+    AtomicPtr::new(ptr::null_mut()),
+];
+
+/// Installs the signal handlers. Only the first call has an effect.
+pub(crate) fn init() {
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| unsafe {
+        for (&signum, previous) in SIGNALS.iter().zip(PREVIOUS.iter()) {
+            install(signum, previous);
+        }
+    });
+}
+
+unsafe fn install(signum: c_int, previous: &AtomicPtr<sigaction>) {
+    let mut action: sigaction = mem::zeroed();
+    action.sa_sigaction = handler as *const () as usize;
+    action.sa_flags = SA_SIGINFO | SA_ONSTACK;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    // Published before our handler is installed, so that the handler can always forward to it.
+    let old_action = Box::into_raw(Box::new(mem::zeroed::<sigaction>()));
+    previous.store(old_action, Ordering::Release);
+    let result = libc::sigaction(signum, &action, old_action);
+    assert_eq!(result, 0, "{}", Error::last_os_error());
+}
+
+/// Registers `running` as the innermost generator running on this thread.
+///
+/// The registration must be undone with `leave` before `running` is moved or dropped.
+#[inline(always)]
+pub(crate) unsafe fn enter(running: &mut Running) {
+    let parent = CURRENT.with(|current| current.replace(running));
+    running.parent = parent;
+    if parent.is_null() && !HAS_ALT_STACK.with(|has| has.get()) {
+        setup_alt_stack();
+    }
+}
+
+/// Restores the registration from before the matching `enter` call.
+#[inline(always)]
+pub(crate) unsafe fn leave(running: &Running) {
+    CURRENT.with(|current| current.set(running.parent));
+}
+
+/// Records the stack pointer of the context that resumed the innermost generator, the fault handler
+/// returns to it. Called by the generator after each switch to it.
+#[inline(always)]
+pub(crate) fn resumed_from(caller: *mut usize) {
+    CURRENT.with(|current| {
+        if let Some(running) = unsafe { current.get().as_ref() } {
+            running.caller.set(caller);
+        }
+    });
+}
+
+#[cold]
+unsafe fn setup_alt_stack() {
+    HAS_ALT_STACK.with(|has| has.set(true));
+
+    let mut existing: libc::stack_t = mem::zeroed();
+    libc::sigaltstack(ptr::null(), &mut existing);
+    if existing.ss_flags & libc::SS_DISABLE == 0 {
+        // Already set up by someone else (e.g. the Rust std library).
+        return;
+    }
+    // Without a signal stack overflows will still crash the process, they will just not be reported.
+    if let Ok(alt_stack) = AltStack::new() {
+        ALT_STACK.with(|slot| slot.set(Some(alt_stack)));
+    }
+}
+
+/// A signal stack owned by switcheroo.
+struct AltStack {
+    ptr: *mut c_void,
+    size: usize,
+}
+
+impl AltStack {
+    const SIZE: usize = 64 * 1024;
+
+    fn new() -> Result<Self, Error> {
+        let size = AltStack::SIZE.max(libc::SIGSTKSZ);
+        unsafe {
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                return Err(Error::last_os_error());
+            }
+            let alt_stack = libc::stack_t {
+                ss_sp: ptr,
+                ss_flags: 0,
+                ss_size: size,
+            };
+            if libc::sigaltstack(&alt_stack, ptr::null_mut()) != 0 {
+                let error = Error::last_os_error();
+                libc::munmap(ptr, size);
+                return Err(error);
+            }
+            Ok(AltStack { ptr, size })
+        }
+    }
+}
+
+impl Drop for AltStack {
+    fn drop(&mut self) {
+        unsafe {
+            let disable = libc::stack_t {
+                ss_sp: ptr::null_mut(),
+                ss_flags: libc::SS_DISABLE,
+                ss_size: 0,
+            };
+            libc::sigaltstack(&disable, ptr::null_mut());
+            libc::munmap(self.ptr, self.size);
+        }
+    }
+}
+
+unsafe extern "C" fn handler(signum: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let address = (*info).si_addr() as usize;
+
+    // The thread local is never lazily initialized, this makes it safe to access here.
+    let running = CURRENT.with(|current| current.get());
+    if let Some(generator) = running.as_ref() {
+        let stack_overflow = generator.guard.contains(&address);
+        let fault = Fault::new(signum, address, stack_overflow);
+        if recover(generator, fault, context) {
+            return;
+        }
+        if stack_overflow {
+            report_overflow(generator.id, address);
+        }
+    }
+
+    let previous = SIGNALS.iter().position(|&s| s == signum).unwrap();
+    forward(
+        signum,
+        info,
+        context,
+        &*PREVIOUS[previous].load(Ordering::Acquire),
+    );
+}
+
+// Jumps back to the `resume` call of the generator, once the signal handler returns.
+#[cfg(any(
+    feature = "ucontext",
+    all(
+        target_os = "linux",
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "x86",
+            target_arch = "arm"
+        )
+    )
+))]
+unsafe fn recover(generator: &Running, fault: Fault, context: *mut c_void) -> bool {
+    let caller = generator.caller.get();
+    // The generator faulted before it got to record where it was resumed from.
+    if caller.is_null() {
+        return false;
+    }
+    generator.fault.set(Some(fault));
+    crate::arch::return_to(context, caller, 0);
+    true
+}
+
+#[cfg(not(any(
+    feature = "ucontext",
+    all(
+        target_os = "linux",
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "riscv64",
+            target_arch = "x86",
+            target_arch = "arm"
+        )
+    )
+)))]
+unsafe fn recover(_generator: &Running, _fault: Fault, _context: *mut c_void) -> bool {
+    false
+}
+
+/// Returns the name of a signal handled by switcheroo.
+pub(crate) fn name(signum: c_int) -> &'static str {
+    match signum {
+        SIGSEGV => "SIGSEGV",
+        SIGBUS => "SIGBUS",
+        SIGILL => "SIGILL",
+        SIGFPE => "SIGFPE",
+        _ => "unknown signal",
+    }
+}
+
+// Passes a signal not caused by a generator to the handler installed before ours.
+unsafe fn forward(signum: c_int, info: *mut siginfo_t, context: *mut c_void, previous: &sigaction) {
+    match previous.sa_sigaction {
+        libc::SIG_DFL | libc::SIG_IGN => {
+            // Restore the default action and return. The faulting instruction will be executed
+            // again and this time the OS will terminate the process.
+            let mut action: sigaction = mem::zeroed();
+            action.sa_sigaction = libc::SIG_DFL;
+            libc::sigaction(signum, &action, ptr::null_mut());
+        }
+        f if previous.sa_flags & SA_SIGINFO != 0 => {
+            let f: unsafe extern "C" fn(c_int, *mut siginfo_t, *mut c_void) = mem::transmute(f);
+            f(signum, info, context)
+        }
+        f => {
+            let f: unsafe extern "C" fn(c_int) = mem::transmute(f);
+            f(signum)
+        }
+    }
+}
+
+// Only async-signal-safe functions can be used here, so the message is assembled by hand.
+unsafe fn report_overflow(id: usize, address: usize) -> ! {
+    let mut message = Message::new();
+    message.push(b"switcheroo stack overflow in generator ");
+    message.push_number(id, 10);
+    message.push(b" (fault address 0x");
+    message.push_number(address, 16);
+    message.push(b")\n");
+    libc::write(
+        libc::STDERR_FILENO,
+        message.buffer.as_ptr() as *const c_void,
+        message.len,
+    );
+    libc::abort();
+}
+
+struct Message {
+    buffer: [u8; 128],
+    len: usize,
+}
+
+impl Message {
+    fn new() -> Self {
+        Message {
+            buffer: [0; 128],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buffer[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn push_number(&mut self, mut n: usize, radix: usize) {
+        let mut digits = [0u8; 20];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = b"0123456789abcdef"[n % radix];
+            n /= radix;
+            if n == 0 {
+                break;
+            }
+        }
+        self.push(&digits[start..]);
+    }
+}
@@ -0,0 +1,244 @@
+use std::io::{self, ErrorKind};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use libc::{
+    c_void, mmap, mprotect, munmap, MAP_ANON, MAP_FAILED, MAP_NORESERVE, MAP_PRIVATE, PROT_NONE,
+    PROT_READ, PROT_WRITE,
+};
+
+use super::budget::Reservation;
+use super::configurable::release_pages;
+#[cfg(target_os = "linux")]
+use super::MADV_GUARD_INSTALL;
+use super::{page_size, round_up, Stack};
+use crate::Error;
+
+/// A fixed number of equally sized stacks carved from one memory reservation.
+///
+/// Each stack created by a [StackBuilder](struct.StackBuilder.html) is a separate mapping and
+/// needs a system call to create and drop it. Processes with hundreds of thousands of stacks also
+/// run into the limit of mappings per process (`vm.max_map_count` on Linux). The arena reserves
+/// the memory for all of its stacks at once, taking a stack out of it and dropping the stack is
+/// just a free list operation.
+///
+/// Each stack is separated from its neighbour by a guard region. On Linux 6.13+ the guard regions
+/// are installed with `MADV_GUARD_INSTALL` and the whole arena stays a single mapping. Older
+/// kernels and other Unix platforms fall back to `mprotect`, which splits the reservation into two
+/// mappings per stack.
+///
+/// Memory touched by a stack stays committed after it's returned to the arena, until
+/// [reclaim](struct.StackArena.html#method.reclaim) is called. The next user of the stack can read
+/// whatever the previous one left on it, unless the arena is set to
+/// [zero](struct.StackArena.html#method.set_zero) returned stacks. Cloning an arena returns a new
+/// handle to the same arena, the memory is released once all handles and stacks are dropped.
+///
+/// Unix only.
+///
+/// ```
+/// use switcheroo::stack::*;
+///
+/// let arena = StackArena::new(64 * 1024, 100).unwrap();
+/// let stack = arena.stack().unwrap();
+/// assert_eq!(stack.size(), 64 * 1024);
+/// assert_eq!(arena.available(), 99);
+/// drop(stack);
+/// assert_eq!(arena.available(), 100);
+/// ```
+#[derive(Clone)]
+pub struct StackArena {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    ptr: *mut u8,
+    // Size of the whole reservation
+    len: usize,
+    stack_size: usize,
+    guard_size: usize,
+    // Indexes of the stacks that are not in use.
+    free: Mutex<Vec<usize>>,
+    zero: AtomicBool,
+    _reservation: Reservation,
+}
+
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
+impl StackArena {
+    /// Reserves memory for `count` stacks of `stack_size` bytes, each with a one page guard region
+    /// on top of it. The size is rounded up to a multiple of the page size.
+    pub fn new(stack_size: usize, count: usize) -> Result<StackArena, Error> {
+        StackArena::with_guard_size(stack_size, page_size(), count)
+    }
+
+    /// Like [new](struct.StackArena.html#method.new), but with a custom guard region size. It's
+    /// rounded up to a multiple of the page size, 0 disables the guard regions.
+    pub fn with_guard_size(
+        stack_size: usize,
+        guard_size: usize,
+        count: usize,
+    ) -> Result<StackArena, Error> {
+        if count == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "an arena needs to hold at least one stack",
+            )
+            .into());
+        }
+        let page_size = page_size();
+        let stack_size = round_up(stack_size, page_size);
+        let guard_size = round_up(guard_size, page_size);
+        let len = (stack_size + guard_size)
+            .checked_mul(count)
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("an arena of {} stacks doesn't fit the address space", count),
+                )
+            })?;
+        let mut reservation = Reservation::new(stack_size * count)?;
+
+        let flags = MAP_PRIVATE | MAP_ANON | MAP_NORESERVE;
+        let ptr = unsafe { mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, flags, -1, 0) };
+        if ptr == MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        reservation.track(ptr as usize..ptr as usize + len);
+        // From here on, dropping the arena on error will unmap the memory.
+        let inner = Inner {
+            ptr: ptr as *mut u8,
+            len,
+            stack_size,
+            guard_size,
+            // Hand out the stacks from the start of the reservation.
+            free: Mutex::new((0..count).rev().collect()),
+            zero: AtomicBool::new(false),
+            _reservation: reservation,
+        };
+        if guard_size > 0 {
+            unsafe { inner.install_guards(count)? };
+        }
+
+        Ok(StackArena {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Takes a stack out of the arena. Returns an error if all stacks are in use.
+    pub fn stack(&self) -> Result<ArenaStack, Error> {
+        match self.inner.free.lock().unwrap().pop() {
+            Some(index) => Ok(ArenaStack {
+                arena: self.inner.clone(),
+                index,
+            }),
+            None => Err(Error::new("all stacks of the arena are in use")),
+        }
+    }
+
+    /// If set, stacks are zeroed when they are returned to the arena, so that no data can leak
+    /// between the users of a stack. Their memory is given back to the OS on the way. Defaults to
+    /// false.
+    pub fn set_zero(&self, zero: bool) {
+        self.inner.zero.store(zero, Ordering::Relaxed);
+    }
+
+    /// Returns the number of stacks that are not in use.
+    pub fn available(&self) -> usize {
+        self.inner.free.lock().unwrap().len()
+    }
+
+    /// Gives the memory backing the stacks that are not in use back to the OS
+    /// (`MADV_DONTNEED`).
+    pub fn reclaim(&self) -> Result<(), Error> {
+        let free = self.inner.free.lock().unwrap();
+        for &index in free.iter() {
+            let top = self.inner.top(index) as *mut c_void;
+            if unsafe { libc::madvise(top, self.inner.stack_size, libc::MADV_DONTNEED) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Inner {
+    // Returns the top of the stack with the given index, its guard region is right before it.
+    fn top(&self, index: usize) -> *mut u8 {
+        unsafe {
+            self.ptr
+                .add(index * (self.stack_size + self.guard_size) + self.guard_size)
+        }
+    }
+
+    unsafe fn install_guards(&self, count: usize) -> Result<(), Error> {
+        let mut protect = !cfg!(target_os = "linux");
+        for index in 0..count {
+            let guard = self.top(index).sub(self.guard_size) as *mut c_void;
+            #[cfg(target_os = "linux")]
+            if !protect && libc::madvise(guard, self.guard_size, MADV_GUARD_INSTALL) != 0 {
+                let error = Error::last_os_error();
+                // Not supported by this kernel, use regular protected pages.
+                if error.raw_os_error() != Some(libc::EINVAL) {
+                    return Err(error);
+                }
+                protect = true;
+            }
+            if protect && mprotect(guard, self.guard_size, PROT_NONE) != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let result = unsafe { munmap(self.ptr as *mut c_void, self.len) };
+        debug_assert_eq!(result, 0);
+    }
+}
+
+/// A stack taken out of a [StackArena](struct.StackArena.html). It goes back to the arena once
+/// dropped.
+pub struct ArenaStack {
+    arena: Arc<Inner>,
+    index: usize,
+}
+
+impl Stack for ArenaStack {
+    fn bottom(&self) -> *mut usize {
+        unsafe {
+            self.arena
+                .top(self.index)
+                .add(self.arena.stack_size)
+                .cast::<usize>()
+        }
+    }
+
+    fn top(&self) -> *mut usize {
+        self.arena.top(self.index).cast::<usize>()
+    }
+
+    fn size(&self) -> usize {
+        self.arena.stack_size
+    }
+
+    fn guard_size(&self) -> usize {
+        self.arena.guard_size
+    }
+}
+
+impl Drop for ArenaStack {
+    fn drop(&mut self) {
+        if self.arena.zero.load(Ordering::Relaxed) {
+            let top = self.arena.top(self.index) as *mut c_void;
+            // A stack that couldn't be zeroed is never handed out again.
+            if unsafe { release_pages(top, self.arena.stack_size, true) }.is_err() {
+                return;
+            }
+        }
+        self.arena.free.lock().unwrap().push(self.index);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::measure;
+use crate::Error;
+
+// `usize::MAX` if no budget is set.
+static BUDGET: AtomicUsize = AtomicUsize::new(usize::MAX);
+static RESERVED: AtomicUsize = AtomicUsize::new(0);
+static TRACK_RESIDENCY: AtomicBool = AtomicBool::new(false);
+// The memory of the built-in stacks created while residency tracking was enabled, by start
+// address.
+static REGIONS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Limits the memory all built-in stacks of the process can reserve together to `bytes`, `None`
+/// removes the limit. There is no limit by default.
+///
+/// Stacks created after the budget is exhausted fail with an `ErrorKind::OutOfMemory` error,
+/// instead of overcommitting memory the system can't back. Lowering the budget below the memory
+/// that is already reserved doesn't affect existing stacks. Stacks that use memory provided by the
+/// caller (e.g. the [BorrowedStack](struct.BorrowedStack.html)) are not accounted for.
+///
+/// A [StackArena](struct.StackArena.html) reserves the memory of all of its stacks at once.
+pub fn set_budget(bytes: Option<usize>) {
+    BUDGET.store(bytes.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// Returns the current [budget](fn.set_budget.html).
+pub fn budget() -> Option<usize> {
+    match BUDGET.load(Ordering::Relaxed) {
+        usize::MAX => None,
+        budget => Some(budget),
+    }
+}
+
+/// Returns the number of bytes reserved by all built-in stacks that are alive.
+pub fn reserved_bytes() -> usize {
+    RESERVED.load(Ordering::Relaxed)
+}
+
+/// Enables or disables keeping track of the built-in stacks for
+/// [resident_bytes](fn.resident_bytes.html). It's disabled by default, because creating and
+/// dropping stacks has to take a process-wide lock while it's enabled.
+///
+/// Only stacks created while tracking is enabled are tracked, until they are dropped.
+pub fn set_track_residency(enabled: bool) {
+    TRACK_RESIDENCY.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns the number of bytes of the tracked built-in stacks that are currently backed by
+/// physical memory, or `None` if [residency tracking](fn.set_track_residency.html) is disabled.
+/// This checks the residency of each stack, so it's best not called too often.
+pub fn resident_bytes() -> Option<usize> {
+    if !TRACK_RESIDENCY.load(Ordering::Relaxed) {
+        return None;
+    }
+    // Copied, so that creating and dropping stacks doesn't wait for the residency checks.
+    let regions: Vec<(usize, usize)> = REGIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&start, &len)| (start, len))
+        .collect();
+    Some(
+        regions
+            .into_iter()
+            .filter_map(|(start, len)| measure::range_resident(start..start + len).ok())
+            .sum(),
+    )
+}
+
+/// Memory reserved from the budget by a stack, it's given back once dropped.
+pub(crate) struct Reservation {
+    bytes: usize,
+    // Start of the tracked memory region.
+    start: Option<usize>,
+    // Only reservations made while residency tracking is enabled track their memory region.
+    tracked: bool,
+}
+
+impl Reservation {
+    /// Reserves `bytes` from the budget. Fails if the budget would be exceeded.
+    pub(crate) fn new(bytes: usize) -> Result<Reservation, Error> {
+        let tracked = TRACK_RESIDENCY.load(Ordering::Relaxed);
+        let budget = BUDGET.load(Ordering::Relaxed);
+        if budget == usize::MAX {
+            RESERVED.fetch_add(bytes, Ordering::Relaxed);
+            return Ok(Reservation {
+                bytes,
+                start: None,
+                tracked,
+            });
+        }
+        RESERVED
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |reserved| {
+                reserved.checked_add(bytes).filter(|&total| total <= budget)
+            })
+            .map_err(|reserved| {
+                io::Error::new(
+                    ErrorKind::OutOfMemory,
+                    format!(
+                        "stack memory budget of {} bytes exhausted, {} bytes are reserved and {} more were requested",
+                        budget, reserved, bytes
+                    ),
+                )
+            })?;
+        Ok(Reservation {
+            bytes,
+            start: None,
+            tracked,
+        })
+    }
+
+    /// Includes the memory of the stack in [resident_bytes](fn.resident_bytes.html), if residency
+    /// tracking was enabled when the memory was reserved.
+    pub(crate) fn track(&mut self, region: Range<usize>) {
+        if !self.tracked {
+            return;
+        }
+        REGIONS
+            .lock()
+            .unwrap()
+            .insert(region.start, region.end - region.start);
+        self.start = Some(region.start);
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            REGIONS.lock().unwrap().remove(&start);
+        }
+        RESERVED.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
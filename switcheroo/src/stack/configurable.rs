@@ -0,0 +1,441 @@
+use std::mem::size_of;
+use std::ptr;
+
+#[cfg(target_family = "unix")]
+use libc::{
+    c_void, mmap, mprotect, munmap, MAP_ANON, MAP_FAILED, MAP_NORESERVE, MAP_PRIVATE, PROT_NONE,
+    PROT_READ, PROT_WRITE,
+};
+
+#[cfg(target_family = "windows")]
+use winapi::ctypes::c_void;
+#[cfg(target_family = "windows")]
+use winapi::um::memoryapi::{VirtualAlloc, VirtualFree, VirtualProtect, VirtualQuery};
+#[cfg(target_family = "windows")]
+use winapi::um::winnt::{
+    MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, MEM_RESET, PAGE_GUARD,
+    PAGE_NOACCESS, PAGE_READWRITE,
+};
+
+use super::budget::Reservation;
+#[cfg(target_family = "unix")]
+use super::page_size;
+#[cfg(target_os = "linux")]
+use super::MADV_GUARD_INSTALL;
+use super::{round_up, NewStack, RecyclableStack, Stack};
+use crate::Error;
+
+#[cfg(target_family = "windows")]
+const PAGE_SIZE: usize = 4096;
+#[cfg(target_family = "windows")]
+const EXCEPTION_ZONE: usize = 4 * PAGE_SIZE;
+
+/// Describes how a [ConfigurableStack](struct.ConfigurableStack.html) should be set up.
+///
+/// The defaults match the [EightMbStack](struct.EightMbStack.html): 8 Mb of usable stack, a guard
+/// page and an overcommitted mapping, with all other options turned off.
+///
+/// ```
+/// use switcheroo::stack::*;
+///
+/// let stack = StackBuilder::new()
+///     .size(256 * 1024)
+///     .prefault_pages(2)
+///     .build()
+///     .unwrap();
+/// assert_eq!(stack.bottom() as usize - stack.top() as usize, 256 * 1024);
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(target_family = "windows", allow(dead_code))]
+pub struct StackBuilder {
+    size: usize,
+    guard_size: Option<usize>,
+    prefault_pages: usize,
+    huge_pages: bool,
+    lock: bool,
+    overcommit: bool,
+}
+
+impl Default for StackBuilder {
+    fn default() -> Self {
+        StackBuilder::new()
+    }
+}
+
+impl StackBuilder {
+    /// Returns a builder with the default configuration.
+    pub fn new() -> Self {
+        StackBuilder {
+            size: 8 * 1024 * 1024,
+            guard_size: None,
+            prefault_pages: 0,
+            huge_pages: false,
+            lock: false,
+            overcommit: true,
+        }
+    }
+
+    /// Sets the usable size of the stack in bytes. It's rounded up to a multiple of the page size.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Sets the size of the guard region on top of the stack in bytes. It's rounded up to a multiple
+    /// of the page size. Defaults to one page, 0 disables the guard region.
+    ///
+    /// Unix only, Windows manages guard pages on its own.
+    pub fn guard_size(mut self, guard_size: usize) -> Self {
+        self.guard_size = Some(guard_size);
+        self
+    }
+
+    /// Commits the bottom `pages` of the stack right away, so that the first function calls don't
+    /// need to page fault. On Linux this uses `MADV_POPULATE_WRITE`.
+    pub fn prefault_pages(mut self, pages: usize) -> Self {
+        self.prefault_pages = pages;
+        self
+    }
+
+    /// Advises the OS to back the stack with transparent huge pages (`MADV_HUGEPAGE`).
+    ///
+    /// Linux only, ignored on other platforms.
+    pub fn huge_pages(mut self, huge_pages: bool) -> Self {
+        self.huge_pages = huge_pages;
+        self
+    }
+
+    /// Locks the whole stack into physical memory (`mlock`), so that latency critical tasks never
+    /// page fault on it. Locked memory is limited by `RLIMIT_MEMLOCK`.
+    ///
+    /// Unix only, ignored on Windows.
+    pub fn lock(mut self, lock: bool) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// If set (the default), the stack memory is only reserved (`MAP_NORESERVE`) and is not
+    /// accounted against the system's commit limit until it's used. Otherwise the whole stack is
+    /// accounted against the commit limit when it's created, so with strict overcommit accounting
+    /// (`vm.overcommit_memory = 2` on Linux) creating it fails if the system can't back it.
+    ///
+    /// Either way physical pages are only allocated once they are touched, use
+    /// [prefault_pages](struct.StackBuilder.html#method.prefault_pages) or
+    /// [lock](struct.StackBuilder.html#method.lock) to allocate them up front.
+    ///
+    /// Unix only, on Windows the stack is always committed as it grows.
+    pub fn overcommit(mut self, overcommit: bool) -> Self {
+        self.overcommit = overcommit;
+        self
+    }
+
+    /// Creates a new stack with this configuration.
+    #[cfg(target_family = "unix")]
+    pub fn build(&self) -> Result<ConfigurableStack, Error> {
+        let page_size = page_size();
+        let size = round_up(self.size, page_size);
+        let guard_size = round_up(self.guard_size.unwrap_or(page_size), page_size);
+        let len = guard_size + size;
+        let mut reservation = Reservation::new(size)?;
+
+        // Miri supports neither `MAP_NORESERVE` nor `mprotect`. Under Miri the generators run on
+        // their own threads (the `thread` feature) and never touch the stack memory anyway.
+        let mut flags = MAP_PRIVATE | MAP_ANON;
+        if self.overcommit && !cfg!(miri) {
+            flags |= MAP_NORESERVE;
+        }
+        let ptr = unsafe { mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, flags, -1, 0) };
+        if ptr == MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        reservation.track(ptr as usize + guard_size..ptr as usize + len);
+        // From here on, dropping the stack on error will unmap the memory.
+        let stack = ConfigurableStack {
+            ptr: ptr as *mut usize,
+            len,
+            guard_size,
+            locked: self.lock,
+            _reservation: reservation,
+        };
+
+        unsafe {
+            // Turn the lowest pages into a guard region, growing into it will trigger a SIGSEGV.
+            if guard_size > 0 && !cfg!(miri) {
+                install_guard(ptr, guard_size)?;
+            }
+
+            let prefault = (self.prefault_pages * page_size).min(size);
+            if prefault > 0 {
+                stack.prefault(prefault)?;
+            }
+
+            let top = stack.top() as *mut c_void;
+            if self.lock && libc::mlock(top, size) != 0 {
+                return Err(Error::last_os_error());
+            }
+
+            // Last, so that nothing above replaces the memory and drops the advice.
+            if self.huge_pages {
+                advise_huge_pages(top, size)?;
+            }
+        }
+
+        Ok(stack)
+    }
+
+    /// Creates a new stack with this configuration.
+    #[cfg(target_family = "windows")]
+    pub fn build(&self) -> Result<ConfigurableStack, Error> {
+        let size = round_up(self.size, PAGE_SIZE);
+        let len = size + EXCEPTION_ZONE;
+        let mut reservation = Reservation::new(size)?;
+        // At least one page is always committed.
+        let committed = self.prefault_pages.max(1).min(size / PAGE_SIZE) * PAGE_SIZE;
+        unsafe {
+            // Add extra 16 Kb on top of the stack to be used by the exception handler in case of a stack overflow.
+            // Cast pointer to `usize`, because calculating offsets with `c_void` is impossible. Sometimes it has a
+            // size of 0, sometimes it decides to be 1 byte.
+            let ptr = VirtualAlloc(ptr::null_mut(), len, MEM_RESERVE, PAGE_NOACCESS) as *mut usize;
+            if ptr.is_null() {
+                return Err(Error::last_os_error());
+            }
+            reservation.track(ptr as usize + EXCEPTION_ZONE..ptr as usize + len);
+            // From here on, dropping the stack on error will free the memory.
+            let stack = ConfigurableStack {
+                ptr,
+                len,
+                _reservation: reservation,
+            };
+
+            // Commit the bottom pages (the prefaulted ones + 2 guard pages)
+            let bottom_2 = VirtualAlloc(
+                ptr.add((len - committed - 2 * PAGE_SIZE) / size_of::<usize>()) as *mut c_void,
+                committed + 2 * PAGE_SIZE,
+                MEM_COMMIT,
+                PAGE_GUARD | PAGE_READWRITE,
+            );
+            if bottom_2.is_null() {
+                return Err(Error::last_os_error());
+            }
+
+            let old_protect: u32 = 0;
+            let bottom_1 = VirtualProtect(
+                ptr.add((len - committed) / size_of::<usize>()) as *mut c_void,
+                committed,
+                PAGE_READWRITE,
+                &old_protect as *const u32 as *mut u32,
+            );
+            if bottom_1 == 0 {
+                return Err(Error::last_os_error());
+            }
+
+            Ok(stack)
+        }
+    }
+}
+
+// Prefers `MADV_GUARD_INSTALL`, so that the stack and its guard region stay a single mapping and
+// many stacks don't run into the `vm.max_map_count` limit. Older kernels fall back to `mprotect`.
+#[cfg(target_os = "linux")]
+unsafe fn install_guard(ptr: *mut c_void, size: usize) -> Result<(), Error> {
+    if libc::madvise(ptr, size, MADV_GUARD_INSTALL) == 0 {
+        return Ok(());
+    }
+    let error = Error::last_os_error();
+    // Not supported by this kernel, use regular protected pages.
+    if error.raw_os_error() != Some(libc::EINVAL) {
+        return Err(error);
+    }
+    if mprotect(ptr, size, PROT_NONE) != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+unsafe fn install_guard(ptr: *mut c_void, size: usize) -> Result<(), Error> {
+    if mprotect(ptr, size, PROT_NONE) != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn advise_huge_pages(ptr: *mut c_void, size: usize) -> Result<(), Error> {
+    if libc::madvise(ptr, size, libc::MADV_HUGEPAGE) != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+unsafe fn advise_huge_pages(_ptr: *mut c_void, _size: usize) -> Result<(), Error> {
+    Ok(())
+}
+
+/// A stack created from a [StackBuilder](struct.StackBuilder.html).
+///
+/// On Unix platforms the memory is reserved with mmap and the guard region on top of it is
+/// installed with `MADV_GUARD_INSTALL` (Linux 6.13+) or protected with PROT_NONE. Overflowing into
+/// it raises a fault instead of silently overwriting other memory.
+///
+/// On Windows it will reserve the stack + 4 pages on top for the exception handler. Only the
+/// bottom of the stack will be marked as commited, while the rest will be reserved. The memory is
+/// specifically set up with guard pages in a way that Windows expect it to be, so that the OS can
+/// automatically grow and commit memory.
+pub struct ConfigurableStack {
+    ptr: *mut usize,
+    // Size of the whole mapping
+    len: usize,
+    #[cfg(target_family = "unix")]
+    guard_size: usize,
+    #[cfg(target_family = "unix")]
+    locked: bool,
+    // Accounts the stack against the memory budget.
+    _reservation: Reservation,
+}
+
+unsafe impl Send for ConfigurableStack {}
+
+impl ConfigurableStack {
+    // Backs the bottom `size` bytes of the stack with memory. On Linux 5.14+ this is done with
+    // `MADV_POPULATE_WRITE`, everywhere else by touching each page.
+    #[cfg(target_family = "unix")]
+    unsafe fn prefault(&self, size: usize) -> Result<(), Error> {
+        let start = self.bottom() as usize - size;
+        #[cfg(target_os = "linux")]
+        {
+            if libc::madvise(start as *mut c_void, size, libc::MADV_POPULATE_WRITE) == 0 {
+                return Ok(());
+            }
+            let error = Error::last_os_error();
+            // Not supported by this kernel, touch the pages instead.
+            if error.raw_os_error() != Some(libc::EINVAL) {
+                return Err(error);
+            }
+        }
+        for page in (start..start + size).step_by(page_size()) {
+            (page as *mut u8).write_volatile(0);
+        }
+        Ok(())
+    }
+}
+
+impl NewStack for ConfigurableStack {
+    type Params = StackBuilder;
+
+    fn with_params(params: StackBuilder) -> Result<Self, Error> {
+        params.build()
+    }
+}
+
+impl RecyclableStack for ConfigurableStack {
+    #[cfg(target_family = "unix")]
+    fn reclaim(&self, zero: bool) -> Result<(), Error> {
+        let top = self.top() as *mut c_void;
+        let size = self.size();
+        unsafe {
+            if self.locked {
+                // Locked pages can't be released, they stay resident until the stack is dropped.
+                if zero {
+                    ptr::write_bytes(top as *mut u8, 0, size);
+                }
+                return Ok(());
+            }
+            release_pages(top, size, zero)
+        }
+    }
+
+    #[cfg(target_family = "windows")]
+    fn reclaim(&self, zero: bool) -> Result<(), Error> {
+        unsafe {
+            // The committed part of the stack is a single region that ends at the bottom.
+            let mut info: MEMORY_BASIC_INFORMATION = std::mem::zeroed();
+            let last_page = (self.bottom() as usize - PAGE_SIZE) as *const c_void;
+            if VirtualQuery(last_page, &mut info, size_of::<MEMORY_BASIC_INFORMATION>()) == 0 {
+                return Err(Error::last_os_error());
+            }
+            let start = info.BaseAddress;
+            let len = self.bottom() as usize - start as usize;
+            if zero {
+                ptr::write_bytes(start as *mut u8, 0, len);
+            } else if VirtualAlloc(start, len, MEM_RESET, PAGE_READWRITE).is_null() {
+                return Err(Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+}
+
+// Private anonymous mappings are refilled with zeroes after `MADV_DONTNEED` on Linux.
+#[cfg(target_os = "linux")]
+pub(super) unsafe fn release_pages(
+    ptr: *mut c_void,
+    size: usize,
+    _zero: bool,
+) -> Result<(), Error> {
+    if libc::madvise(ptr, size, libc::MADV_DONTNEED) != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Other platforms may keep the old content after `MADV_DONTNEED`, zeroing is done by replacing the
+// pages with a fresh mapping.
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+pub(super) unsafe fn release_pages(ptr: *mut c_void, size: usize, zero: bool) -> Result<(), Error> {
+    if zero {
+        let flags = MAP_PRIVATE | MAP_ANON | libc::MAP_FIXED;
+        if mmap(ptr, size, PROT_READ | PROT_WRITE, flags, -1, 0) == MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+    } else if libc::madvise(ptr, size, libc::MADV_DONTNEED) != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Stack for ConfigurableStack {
+    #[cfg(target_family = "unix")]
+    fn bottom(&self) -> *mut usize {
+        unsafe { self.ptr.add(self.len / size_of::<usize>()) }
+    }
+    #[cfg(target_family = "unix")]
+    fn top(&self) -> *mut usize {
+        unsafe { self.ptr.add(self.guard_size / size_of::<usize>()) }
+    }
+    #[cfg(target_family = "unix")]
+    fn guard_size(&self) -> usize {
+        self.guard_size
+    }
+
+    // Windows
+    #[cfg(target_family = "windows")]
+    fn bottom(&self) -> *mut usize {
+        unsafe { self.ptr.add(self.len / size_of::<usize>()) }
+    }
+    #[cfg(target_family = "windows")]
+    fn top(&self) -> *mut usize {
+        unsafe { self.ptr.add(EXCEPTION_ZONE / size_of::<usize>()) }
+    }
+    #[cfg(target_family = "windows")]
+    fn deallocation(&self) -> *mut usize {
+        self.ptr
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl Drop for ConfigurableStack {
+    fn drop(&mut self) {
+        let result = unsafe { munmap(self.ptr as *mut c_void, self.len) };
+        debug_assert_eq!(result, 0);
+    }
+}
+
+#[cfg(target_family = "windows")]
+impl Drop for ConfigurableStack {
+    fn drop(&mut self) {
+        let result = unsafe { VirtualFree(self.ptr as *mut c_void, 0, MEM_RELEASE) };
+        debug_assert_ne!(result, 0);
+    }
+}
@@ -0,0 +1,75 @@
+use std::alloc::{alloc, dealloc, Layout};
+use std::io::{self, ErrorKind};
+
+use super::budget::Reservation;
+use super::{NewStack, Stack, MIN_STACK_SIZE, STACK_ALIGNMENT};
+use crate::Error;
+
+/// A stack allocated with the global allocator.
+///
+/// It doesn't use any OS specific memory calls, this makes it usable in environments where they
+/// are not available or restricted, like Miri or some sandboxes. The memory is not zeroed and
+/// there is no guard region, overflowing the stack silently overwrites other memory.
+///
+/// [NewStack::new](trait.NewStack.html#method.new) creates a 1 Mb stack, other sizes can be
+/// created with [with_size](struct.HeapStack.html#method.with_size).
+pub struct HeapStack {
+    ptr: *mut u8,
+    layout: Layout,
+    _reservation: Reservation,
+}
+
+unsafe impl Send for HeapStack {}
+
+const ONE_MB: usize = 1024 * 1024;
+
+impl HeapStack {
+    /// Allocates a stack of `size` bytes. The size is rounded up to a multiple of
+    /// [STACK_ALIGNMENT](constant.STACK_ALIGNMENT.html), sizes below
+    /// [MIN_STACK_SIZE](constant.MIN_STACK_SIZE.html) are raised to it. Check
+    /// [size](trait.Stack.html#method.size) for the size of the allocated stack.
+    pub fn with_size(size: usize) -> Result<HeapStack, Error> {
+        let size = size.max(MIN_STACK_SIZE).div_ceil(STACK_ALIGNMENT) * STACK_ALIGNMENT;
+        let layout = Layout::from_size_align(size, STACK_ALIGNMENT)
+            .map_err(|error| io::Error::new(ErrorKind::InvalidInput, error))?;
+        let mut reservation = Reservation::new(size)?;
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() {
+            return Err(io::Error::new(
+                ErrorKind::OutOfMemory,
+                format!("failed to allocate a stack of {} bytes", size),
+            )
+            .into());
+        }
+        reservation.track(ptr as usize..ptr as usize + size);
+        Ok(HeapStack {
+            ptr,
+            layout,
+            _reservation: reservation,
+        })
+    }
+}
+
+impl NewStack for HeapStack {
+    type Params = ();
+
+    fn with_params(_params: ()) -> Result<Self, Error> {
+        HeapStack::with_size(ONE_MB)
+    }
+}
+
+impl Stack for HeapStack {
+    fn bottom(&self) -> *mut usize {
+        unsafe { self.ptr.add(self.layout.size()) as *mut usize }
+    }
+
+    fn top(&self) -> *mut usize {
+        self.ptr as *mut usize
+    }
+}
+
+impl Drop for HeapStack {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
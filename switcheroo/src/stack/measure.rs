@@ -0,0 +1,116 @@
+use std::mem::size_of;
+use std::ops::Range;
+use std::ptr;
+
+use super::Stack;
+use crate::Error;
+
+// Each word of a painted stack contains this pattern until it's used.
+const PAINT: usize = 0x5a5a_5a5a_5a5a_5a5a_u64 as usize;
+
+/// How a [generator](../struct.Generator.html) measures the stack usage of its closure, see
+/// [Generator::stack_high_water_mark](../struct.Generator.html#method.stack_high_water_mark).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measurement {
+    /// The whole stack is filled with a known pattern before the generator starts, words that
+    /// don't contain the pattern anymore were used. This is exact up to a word, but commits the
+    /// whole stack to memory.
+    Paint,
+    /// The pages of the stack that are backed by physical memory are checked with `mincore`. This
+    /// is exact up to a page and doesn't cost anything up front, but the stack needs to be fresh
+    /// (or returned to a [StackPool](struct.StackPool.html)) and pages committed up front, e.g.
+    /// with [prefault_pages](struct.StackBuilder.html#method.prefault_pages), count as used.
+    ///
+    /// On Windows the committed part of the stack is checked instead.
+    Residency,
+}
+
+/// Prepares `stack` to be measured, before the generator uses it.
+pub(crate) fn prepare<S: Stack>(stack: &S, measurement: Measurement) {
+    if measurement == Measurement::Paint {
+        unsafe { ptr::write_bytes(stack.top() as *mut u8, PAINT as u8, stack.size()) };
+    }
+}
+
+/// Returns the number of bytes at the bottom of `stack` that were used, or `None` if the residency
+/// of the stack can't be checked.
+pub(crate) fn high_water_mark<S: Stack>(stack: &S, measurement: Measurement) -> Option<usize> {
+    match measurement {
+        Measurement::Paint => {
+            let words = stack.size() / size_of::<usize>();
+            let unused = (0..words)
+                .take_while(|&i| unsafe { stack.top().add(i).read_volatile() } == PAINT)
+                .count();
+            Some((words - unused) * size_of::<usize>())
+        }
+        Measurement::Residency => {
+            let pages = resident_pages(stack.usable_range()).ok()?;
+            let used = match pages.iter().position(|&resident| resident) {
+                Some(first) => (pages.len() - first) * page_size(),
+                None => 0,
+            };
+            Some(used.min(stack.size()))
+        }
+    }
+}
+
+/// Returns the number of bytes of `stack` that are backed by physical memory.
+pub(crate) fn bytes_resident<S: Stack>(stack: &S) -> Result<usize, Error> {
+    range_resident(stack.usable_range())
+}
+
+/// Returns the number of bytes inside of `range` that are backed by physical memory.
+pub(crate) fn range_resident(range: Range<usize>) -> Result<usize, Error> {
+    let pages = resident_pages(range)?;
+    Ok(pages.iter().filter(|&&resident| resident).count() * page_size())
+}
+
+// Returns for each page inside of `range` if it's resident.
+#[cfg(target_family = "unix")]
+fn resident_pages(range: Range<usize>) -> Result<Vec<bool>, Error> {
+    let page_size = page_size();
+    // `mincore` needs a page aligned start address.
+    let start = range.start / page_size * page_size;
+    let len = range.end - start;
+    let mut pages = vec![0u8; len.div_ceil(page_size)];
+    if unsafe { libc::mincore(start as *mut libc::c_void, len, pages.as_mut_ptr() as _) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(pages.iter().map(|&page| page & 1 != 0).collect())
+}
+
+// Committed pages count as resident on Windows.
+#[cfg(target_family = "windows")]
+fn resident_pages(range: Range<usize>) -> Result<Vec<bool>, Error> {
+    use winapi::um::memoryapi::VirtualQuery;
+    use winapi::um::winnt::{MEMORY_BASIC_INFORMATION, MEM_COMMIT};
+
+    let page_size = page_size();
+    let start = range.start / page_size * page_size;
+    let mut pages = Vec::with_capacity((range.end - start).div_ceil(page_size));
+    let mut address = start;
+    while address < range.end {
+        let mut info: MEMORY_BASIC_INFORMATION = unsafe { std::mem::zeroed() };
+        let size = size_of::<MEMORY_BASIC_INFORMATION>();
+        if unsafe { VirtualQuery(address as *const _, &mut info, size) } == 0 {
+            return Err(Error::last_os_error());
+        }
+        // The region has the same state from `address` until its end.
+        let region_end = (info.BaseAddress as usize + info.RegionSize).min(range.end);
+        while address < region_end {
+            pages.push(info.State == MEM_COMMIT);
+            address += page_size;
+        }
+    }
+    Ok(pages)
+}
+
+#[cfg(target_family = "unix")]
+fn page_size() -> usize {
+    super::page_size()
+}
+
+#[cfg(target_family = "windows")]
+fn page_size() -> usize {
+    4096
+}
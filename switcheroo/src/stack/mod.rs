@@ -1,28 +1,183 @@
-//! Different stack implementations (currently only contains a 8 Mb stack).
+//! Different stack implementations.
+//!
+//! The [StackBuilder](struct.StackBuilder.html) creates stacks with a custom size and memory
+//! setup. The [EightMbStack](struct.EightMbStack.html) and [OneMbStack](struct.OneMbStack.html)
+//! are presets of it.
+//!
+//! A [StackArena](struct.StackArena.html) carves many stacks out of a single memory reservation.
+//!
+//! A [StackPool](struct.StackPool.html) keeps stacks around after they are used, so that they can
+//! be reused by the next generator.
+//!
+//! The [RawStack](struct.RawStack.html) and [BorrowedStack](struct.BorrowedStack.html) use memory
+//! provided by the caller, the [HeapStack](struct.HeapStack.html) is allocated with the global
+//! allocator.
+//!
+//! The memory of all built-in stacks can be limited with a process-wide
+//! [budget](fn.set_budget.html).
+//!
+//! Without the `std` feature only the [RawStack](struct.RawStack.html), the
+//! [BorrowedStack](struct.BorrowedStack.html) and user-provided implementations of the
+//! [Stack](trait.Stack.html) trait are available.
 
+#[cfg(all(target_family = "unix", feature = "std"))]
+mod arena;
+#[cfg(feature = "std")]
+mod budget;
+#[cfg(feature = "std")]
+mod configurable;
+#[cfg(feature = "std")]
 mod eight_mb;
+#[cfg(feature = "std")]
+mod heap;
+#[cfg(feature = "std")]
+pub(crate) mod measure;
+#[cfg(feature = "std")]
 mod one_mb;
+#[cfg(feature = "std")]
+mod pool;
+mod raw;
+#[cfg(all(target_family = "unix", feature = "std"))]
+pub use arena::{ArenaStack, StackArena};
+#[cfg(feature = "std")]
+pub use budget::{budget, reserved_bytes, resident_bytes, set_budget, set_track_residency};
+#[cfg(feature = "std")]
+pub use configurable::{ConfigurableStack, StackBuilder};
+#[cfg(feature = "std")]
 pub use eight_mb::EightMbStack;
+#[cfg(feature = "std")]
+pub use heap::HeapStack;
+#[cfg(feature = "std")]
+pub use measure::Measurement;
+#[cfg(feature = "std")]
 pub use one_mb::OneMbStack;
+#[cfg(feature = "std")]
+pub use pool::{PoolBuilder, PoolStats, RecyclableStack, StackPool};
+pub use raw::{BorrowedStack, RawStack};
 
-/// An implementation of this trait will be accepted by a [generator](struct.Generator.html) as a
+use alloc::format;
+use alloc::string::String;
+use core::ops::Range;
+
+use crate::Error;
+
+/// Alignment the [bottom](trait.Stack.html#tymethod.bottom) of each stack needs to have.
+pub const STACK_ALIGNMENT: usize = 16;
+
+/// The smallest stack a [generator](../struct.Generator.html) accepts. It needs to fit the initial
+/// frame set up by the generator and the entry function of the generator.
+pub const MIN_STACK_SIZE: usize = 4 * 1024;
+
+/// An implementation of this trait will be accepted by a [generator](../struct.Generator.html) as a
 /// valid Stack. Most of the functions provided here are straightforward except for
-/// [deallocation](trait.Stack.html#tymethod.deallocation), this is a Windows only construct.
+/// [deallocation](trait.Stack.html#method.deallocation), this is a Windows only construct.
 ///
 /// Windows reserves a few pages above the stack top, so if a stack overflow exception is triggered
 /// the handler has still enough of stack to process it. The name comes from the fact that it
 /// points to the top most address of the memory area designated to the stack and will be used as a
 /// pointer when freeing/deallocating the stack.
+///
+/// Creating a stack is not part of this trait, stacks that allocate their own memory also
+/// implement [NewStack](trait.NewStack.html).
 pub trait Stack: Sized + Send {
-    /// Returns a new stack.
-    fn new() -> Result<Self, std::io::Error>;
-
-    /// Returns a pointer to the bottom of the stack.
+    /// Returns a pointer to the bottom of the stack. It needs to be aligned to
+    /// [STACK_ALIGNMENT](constant.STACK_ALIGNMENT.html).
     fn bottom(&self) -> *mut usize;
 
     /// Returns a pointer to the top of the stack.
     fn top(&self) -> *mut usize;
 
-    /// Returns a pointer to the deallocation stack (a Windows construct).
-    fn deallocation(&self) -> *mut usize;
+    /// Returns a pointer to the deallocation stack (a Windows construct). Defaults to the
+    /// [top](trait.Stack.html#tymethod.top) of the stack, it's ignored on other platforms.
+    fn deallocation(&self) -> *mut usize {
+        self.top()
+    }
+
+    /// Returns the usable size of the stack in bytes.
+    fn size(&self) -> usize {
+        self.bottom() as usize - self.top() as usize
+    }
+
+    /// Returns the size of the inaccessible guard region that ends at
+    /// [top](trait.Stack.html#tymethod.top).
+    ///
+    /// Faults inside this region are reported as a stack overflow of the generator running on
+    /// the stack. Stacks without a guard region can rely on the default implementation.
+    fn guard_size(&self) -> usize {
+        0
+    }
+
+    /// Returns the address range the code running on the stack can use.
+    fn usable_range(&self) -> Range<usize> {
+        self.top() as usize..self.bottom() as usize
+    }
+}
+
+/// Stacks that allocate their own memory.
+pub trait NewStack: Stack {
+    /// Parameters describing the stack to create.
+    type Params: Default;
+
+    /// Returns a new stack created from `params`.
+    fn with_params(params: Self::Params) -> Result<Self, Error>;
+
+    /// Returns a new stack created from the default parameters.
+    fn new() -> Result<Self, Error> {
+        Self::with_params(Self::Params::default())
+    }
+}
+
+/// Checks if a generator can use `stack`.
+pub(crate) fn validate<S: Stack>(stack: &S) -> Result<(), Error> {
+    if !(stack.bottom() as usize).is_multiple_of(STACK_ALIGNMENT) {
+        return Err(invalid_input(format!(
+            "stack bottom {:p} is not aligned to {} bytes",
+            stack.bottom(),
+            STACK_ALIGNMENT
+        )));
+    }
+    if stack.top() > stack.bottom() || stack.size() < MIN_STACK_SIZE {
+        return Err(invalid_input(format!(
+            "stack is too small, it needs to be at least {} bytes",
+            MIN_STACK_SIZE
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn invalid_input(message: String) -> Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn invalid_input(message: String) -> Error {
+    Error::new(message)
+}
+
+// Rounds `size` up to a multiple of `page_size`.
+#[cfg(feature = "std")]
+pub(crate) fn round_up(size: usize, page_size: usize) -> usize {
+    size.div_ceil(page_size) * page_size
+}
+
+// Turns pages into lightweight guard regions that don't split the mapping (Linux 6.13+).
+#[cfg(all(target_os = "linux", feature = "std"))]
+pub(crate) const MADV_GUARD_INSTALL: libc::c_int = 102;
+
+/// Returns the size of a memory page, the smallest unit a guard region can be made of.
+#[cfg(all(target_family = "unix", feature = "std"))]
+pub(crate) fn page_size() -> usize {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    match PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => {
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+            PAGE_SIZE.store(page_size, Ordering::Relaxed);
+            page_size
+        }
+        page_size => page_size,
+    }
 }
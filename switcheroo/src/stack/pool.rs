@@ -0,0 +1,377 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use super::{EightMbStack, NewStack};
+use crate::Error;
+
+// Source of the pool ids, they are used to find the thread caches of a pool.
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local!(
+    // Stacks cached by the current thread, one entry for each pool it used.
+    static THREAD_CACHES: RefCell<Vec<Box<dyn CacheEntry>>> = const { RefCell::new(Vec::new()) };
+);
+
+/// Stacks that can be kept in a [StackPool](struct.StackPool.html).
+pub trait RecyclableStack: NewStack + 'static {
+    /// Gives the memory backing the stack back to the OS. The stack stays usable, its pages are
+    /// committed again once they are written to.
+    ///
+    /// If `zero` is set the whole stack needs to read as zeroes afterwards, so that no data can
+    /// leak to the next user of the stack.
+    fn reclaim(&self, zero: bool) -> Result<(), Error>;
+}
+
+/// Describes how a [StackPool](struct.StackPool.html) should be set up.
+///
+/// ```
+/// use switcheroo::stack::*;
+///
+/// let pool: StackPool<OneMbStack> = PoolBuilder::new().capacity(16).zero(true).build();
+/// let stack = pool.get().unwrap();
+/// pool.put(stack);
+/// assert_eq!(pool.stats().stacks_retained, 1);
+/// ```
+pub struct PoolBuilder<S: RecyclableStack = EightMbStack> {
+    params: S::Params,
+    capacity: usize,
+    thread_cache: usize,
+    zero: bool,
+}
+
+impl<S: RecyclableStack> Default for PoolBuilder<S> {
+    fn default() -> Self {
+        PoolBuilder::new()
+    }
+}
+
+impl<S: RecyclableStack> PoolBuilder<S> {
+    /// Returns a builder with the default configuration. The pool retains up to 256 stacks, 8 of
+    /// them in each thread's cache, and creates new stacks from the default parameters.
+    pub fn new() -> Self {
+        PoolBuilder {
+            params: S::Params::default(),
+            capacity: 256,
+            thread_cache: 8,
+            zero: false,
+        }
+    }
+
+    /// Sets the parameters new stacks are created with.
+    pub fn stack_params(mut self, params: S::Params) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Sets the maximum number of stacks the pool retains, stacks returned to a full pool are
+    /// dropped. This limit covers all thread caches.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the number of stacks each thread keeps for itself, so that most stacks can be taken
+    /// and returned without contending with other threads. 0 disables the thread caches.
+    pub fn thread_cache(mut self, thread_cache: usize) -> Self {
+        self.thread_cache = thread_cache;
+        self
+    }
+
+    /// If set, returned stacks are zeroed, so that no data can leak between the users of a stack.
+    /// Defaults to false.
+    ///
+    /// On Linux this is free, pages released with `MADV_DONTNEED` are always zeroed.
+    pub fn zero(mut self, zero: bool) -> Self {
+        self.zero = zero;
+        self
+    }
+}
+
+impl<S: RecyclableStack> PoolBuilder<S>
+where
+    S::Params: Clone,
+{
+    /// Creates a new pool with this configuration. No stacks are created up front.
+    pub fn build(&self) -> StackPool<S> {
+        StackPool {
+            shared: Arc::new(Shared {
+                id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+                params: self.params.clone(),
+                capacity: self.capacity,
+                thread_cache: self.thread_cache,
+                zero: self.zero,
+                stacks: Mutex::new(Vec::new()),
+                thread_caches: Mutex::new(Vec::new()),
+                retained: AtomicUsize::new(0),
+                bytes_retained: AtomicUsize::new(0),
+                hits: AtomicUsize::new(0),
+                misses: AtomicUsize::new(0),
+                discarded: AtomicUsize::new(0),
+            }),
+        }
+    }
+}
+
+/// A thread-safe pool of stacks.
+///
+/// Creating a stack requires a system call to map its memory and another one to unmap it once it's
+/// dropped. A pool keeps stacks around after they are used, so that short-lived generators can
+/// reuse them. Stacks can be returned to the pool once they are taken out of the generator with
+/// [Generator::stack](../struct.Generator.html#method.stack).
+///
+/// The pages touched by the previous user are released when the stack is returned (e.g. with
+/// `MADV_DONTNEED` on Unix), so retained stacks only hold on to address space. Stacks are cached
+/// per thread first and shared between all threads once a thread's cache is full. The stacks
+/// cached by a thread are handed over to the other threads when it exits. Once the last handle to
+/// the pool is dropped, all retained stacks are dropped, including the ones cached by other
+/// threads.
+///
+/// Cloning a pool returns a new handle to the same pool.
+///
+/// ```
+/// use switcheroo::stack::*;
+/// use switcheroo::{Generator, GeneratorState};
+///
+/// let pool: StackPool = StackPool::new();
+/// for i in 0..10 {
+///     let stack = pool.get().unwrap();
+///     let mut generator = Generator::new(stack, |yielder, input| {
+///         yielder.suspend(input * 2);
+///     })
+///     .unwrap();
+///     assert_eq!(generator.resume(i), Ok(GeneratorState::Yielded(i * 2)));
+///     pool.put(generator.stack());
+/// }
+/// assert_eq!(pool.stats().misses, 1);
+/// assert_eq!(pool.stats().hits, 9);
+/// ```
+pub struct StackPool<S: RecyclableStack = EightMbStack> {
+    shared: Arc<Shared<S>>,
+}
+
+impl<S: RecyclableStack> Clone for StackPool<S> {
+    fn clone(&self) -> Self {
+        StackPool {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<S: RecyclableStack> Default for StackPool<S>
+where
+    S::Params: Clone,
+{
+    fn default() -> Self {
+        StackPool::new()
+    }
+}
+
+impl<S: RecyclableStack> StackPool<S>
+where
+    S::Params: Clone,
+{
+    /// Returns a new pool with the default [configuration](struct.PoolBuilder.html).
+    pub fn new() -> Self {
+        PoolBuilder::new().build()
+    }
+
+    /// Returns a stack from the pool, or creates a new one if the pool is empty.
+    pub fn get(&self) -> Result<S, Error> {
+        let shared = &self.shared;
+        let cached = with_thread_cache(shared, |stacks| stacks.pop())
+            .flatten()
+            .or_else(|| shared.stacks.lock().unwrap().pop());
+
+        match cached {
+            Some(stack) => {
+                shared.retained.fetch_sub(1, Ordering::Relaxed);
+                shared
+                    .bytes_retained
+                    .fetch_sub(stack.size(), Ordering::Relaxed);
+                shared.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(stack)
+            }
+            None => {
+                shared.misses.fetch_add(1, Ordering::Relaxed);
+                S::with_params(shared.params.clone())
+            }
+        }
+    }
+
+    /// Returns a stack to the pool.
+    ///
+    /// The memory backing the stack is released first. If the pool is full or the memory can't be
+    /// released, the stack is dropped instead.
+    pub fn put(&self, stack: S) {
+        let shared = &self.shared;
+        let capacity = shared.capacity;
+        let reserved =
+            shared
+                .retained
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |retained| {
+                    if retained < capacity {
+                        Some(retained + 1)
+                    } else {
+                        None
+                    }
+                });
+        if reserved.is_err() || stack.reclaim(shared.zero).is_err() {
+            if reserved.is_ok() {
+                shared.retained.fetch_sub(1, Ordering::Relaxed);
+            }
+            shared.discarded.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        shared
+            .bytes_retained
+            .fetch_add(stack.size(), Ordering::Relaxed);
+
+        let mut stack = Some(stack);
+        with_thread_cache(shared, |stacks| {
+            if stacks.len() < shared.thread_cache {
+                stacks.push(stack.take().unwrap());
+            }
+        });
+        if let Some(stack) = stack {
+            shared.stacks.lock().unwrap().push(stack);
+        }
+    }
+
+    /// Returns statistics about the usage of the pool.
+    pub fn stats(&self) -> PoolStats {
+        let shared = &self.shared;
+        PoolStats {
+            hits: shared.hits.load(Ordering::Relaxed),
+            misses: shared.misses.load(Ordering::Relaxed),
+            discarded: shared.discarded.load(Ordering::Relaxed),
+            stacks_retained: shared.retained.load(Ordering::Relaxed),
+            bytes_retained: shared.bytes_retained.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Statistics of a [StackPool](struct.StackPool.html).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Number of stacks that were taken from the pool.
+    pub hits: usize,
+    /// Number of stacks that needed to be created, because the pool was empty.
+    pub misses: usize,
+    /// Number of returned stacks that were dropped, because the pool was full or their memory
+    /// couldn't be released.
+    pub discarded: usize,
+    /// Number of stacks currently retained by the pool, including the thread caches.
+    pub stacks_retained: usize,
+    /// Sum of the sizes of all retained stacks in bytes.
+    pub bytes_retained: usize,
+}
+
+// The part of a pool shared between all handles and threads.
+struct Shared<S: RecyclableStack> {
+    id: usize,
+    params: S::Params,
+    capacity: usize,
+    thread_cache: usize,
+    zero: bool,
+    stacks: Mutex<Vec<S>>,
+    // The stacks of all thread caches, so that they can be dropped together with the pool.
+    thread_caches: Mutex<Vec<Weak<Mutex<Vec<S>>>>>,
+    // Number of stacks in `stacks` and all thread caches.
+    retained: AtomicUsize,
+    bytes_retained: AtomicUsize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    discarded: AtomicUsize,
+}
+
+impl<S: RecyclableStack> Drop for Shared<S> {
+    fn drop(&mut self) {
+        // Other threads may never touch their cache again, so their stacks are dropped here
+        // instead of when the threads exit.
+        for stacks in self.thread_caches.get_mut().unwrap().drain(..) {
+            if let Some(stacks) = stacks.upgrade() {
+                stacks.lock().unwrap().clear();
+            }
+        }
+    }
+}
+
+// The stacks a thread cached for one pool. The lock is only contended while the pool is dropped.
+struct ThreadCache<S: RecyclableStack> {
+    pool_id: usize,
+    pool: Weak<Shared<S>>,
+    stacks: Arc<Mutex<Vec<S>>>,
+}
+
+// Allows pools with different stack types to share the thread local.
+trait CacheEntry {
+    fn pool_id(&self) -> usize;
+    // Returns true if the pool was dropped.
+    fn orphaned(&self) -> bool;
+    fn as_any(&mut self) -> &mut dyn Any;
+}
+
+impl<S: RecyclableStack> CacheEntry for ThreadCache<S> {
+    fn pool_id(&self) -> usize {
+        self.pool_id
+    }
+
+    fn orphaned(&self) -> bool {
+        self.pool.strong_count() == 0
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl<S: RecyclableStack> Drop for ThreadCache<S> {
+    fn drop(&mut self) {
+        // Hand the stacks over to the other threads, they are already accounted for.
+        if let Some(shared) = self.pool.upgrade() {
+            let mut stacks = self.stacks.lock().unwrap();
+            shared.stacks.lock().unwrap().append(&mut stacks);
+        }
+    }
+}
+
+// Calls `f` with the current thread's cache for the pool. Returns `None` if thread caches are
+// disabled or the thread is exiting.
+fn with_thread_cache<S, F, R>(shared: &Arc<Shared<S>>, f: F) -> Option<R>
+where
+    S: RecyclableStack,
+    F: FnOnce(&mut Vec<S>) -> R,
+{
+    if shared.thread_cache == 0 {
+        return None;
+    }
+    THREAD_CACHES
+        .try_with(|caches| {
+            let mut caches = caches.borrow_mut();
+            // Caches of dropped pools are removed on the way.
+            caches.retain(|entry| !entry.orphaned());
+            let position = caches.iter().position(|entry| entry.pool_id() == shared.id);
+            let entry = match position {
+                Some(position) => &mut caches[position],
+                None => {
+                    let stacks = Arc::new(Mutex::new(Vec::new()));
+                    let mut thread_caches = shared.thread_caches.lock().unwrap();
+                    // Caches of exited threads are removed on the way.
+                    thread_caches.retain(|stacks| stacks.strong_count() > 0);
+                    thread_caches.push(Arc::downgrade(&stacks));
+                    caches.push(Box::new(ThreadCache {
+                        pool_id: shared.id,
+                        pool: Arc::downgrade(shared),
+                        stacks,
+                    }));
+                    caches.last_mut().unwrap()
+                }
+            };
+            let cache = entry.as_any().downcast_mut::<ThreadCache<S>>().unwrap();
+            let mut stacks = cache.stacks.lock().unwrap();
+            f(&mut stacks)
+        })
+        .ok()
+}
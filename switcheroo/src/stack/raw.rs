@@ -0,0 +1,95 @@
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+
+use super::{Stack, STACK_ALIGNMENT};
+
+/// A stack in memory that is managed by someone else, e.g. a region inside of a wasm linear memory
+/// or a shared memory segment.
+///
+/// The memory is not freed once the stack is dropped. There is no guard region, overflowing the
+/// stack silently overwrites the memory in front of it.
+pub struct RawStack {
+    top: *mut usize,
+    bottom: *mut usize,
+}
+
+unsafe impl Send for RawStack {}
+
+impl RawStack {
+    /// Creates a stack from `len` bytes of memory starting at `ptr`.
+    ///
+    /// The bottom of the stack is aligned down to [STACK_ALIGNMENT](constant.STACK_ALIGNMENT.html)
+    /// and the top is aligned up to the size of a pointer, so the usable stack can be a few bytes
+    /// smaller than `len`.
+    ///
+    /// # Safety
+    ///
+    /// The memory needs to be valid for reads and writes for as long as a generator uses the stack,
+    /// and nothing else may access it during this time.
+    pub unsafe fn from_raw_parts(ptr: *mut u8, len: usize) -> RawStack {
+        let start = ptr as usize;
+        let end = start + len;
+        let bottom = end / STACK_ALIGNMENT * STACK_ALIGNMENT;
+        let top = start.div_ceil(size_of::<usize>()) * size_of::<usize>();
+        RawStack {
+            // Empty stacks get rejected by the generator.
+            top: top.min(bottom) as *mut usize,
+            bottom: bottom as *mut usize,
+        }
+    }
+}
+
+impl Stack for RawStack {
+    fn bottom(&self) -> *mut usize {
+        self.bottom
+    }
+
+    fn top(&self) -> *mut usize {
+        self.top
+    }
+}
+
+/// A stack that borrows its memory, e.g. from a `Box<[MaybeUninit<u8>]>` or a static buffer.
+///
+/// The generator using the stack can't outlive the borrow. Like the
+/// [RawStack](struct.RawStack.html) it has no guard region.
+///
+/// ```
+/// use std::mem::MaybeUninit;
+/// use switcheroo::stack::*;
+/// use switcheroo::{Generator, GeneratorState};
+///
+/// let mut memory = vec![MaybeUninit::uninit(); 64 * 1024];
+/// let stack = BorrowedStack::new(&mut memory);
+/// let mut generator = Generator::new(stack, |yielder, input: u32| {
+///     yielder.suspend(input + 1);
+/// })
+/// .unwrap();
+/// assert_eq!(generator.resume(1), Ok(GeneratorState::Yielded(2)));
+/// ```
+pub struct BorrowedStack<'a> {
+    raw: RawStack,
+    phantom: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a> BorrowedStack<'a> {
+    /// Creates a stack from the borrowed `memory`, aligned the same way as
+    /// [RawStack::from_raw_parts](struct.RawStack.html#method.from_raw_parts).
+    pub fn new(memory: &'a mut [MaybeUninit<u8>]) -> BorrowedStack<'a> {
+        BorrowedStack {
+            // The exclusive borrow guarantees that the memory is valid and unused.
+            raw: unsafe { RawStack::from_raw_parts(memory.as_mut_ptr() as *mut u8, memory.len()) },
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a> Stack for BorrowedStack<'a> {
+    fn bottom(&self) -> *mut usize {
+        self.raw.bottom()
+    }
+
+    fn top(&self) -> *mut usize {
+        self.raw.top()
+    }
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "std")]
+
+use switcheroo::stack::*;
+use switcheroo::{BorrowedInput, BorrowingGenerator, GeneratorState};
+
+// A buffer that is only lent to the generator, it's never cloned.
+struct Buffer;
+
+impl BorrowedInput for Buffer {
+    type Input<'r> = &'r mut String;
+}
+
+#[test]
+fn resume_with_borrowed_input() {
+    let stack = EightMbStack::new().unwrap();
+    let mut generator = BorrowingGenerator::<Buffer, _, _, _>::new(stack, |yielder| {
+        for i in 0..3 {
+            let len = yielder.with_input(|buffer| {
+                buffer.push_str(&i.to_string());
+                buffer.len()
+            });
+            yielder.suspend(len);
+        }
+        yielder.with_input(|buffer| buffer.clear());
+    })
+    .unwrap();
+
+    let mut first = String::from("a");
+    assert_eq!(
+        generator.resume(&mut &mut first),
+        Ok(GeneratorState::Yielded(2))
+    );
+    let mut second = String::new();
+    assert_eq!(
+        generator.resume(&mut &mut second),
+        Ok(GeneratorState::Yielded(1))
+    );
+    assert_eq!(
+        generator.resume(&mut &mut second),
+        Ok(GeneratorState::Yielded(2))
+    );
+    assert_eq!(
+        generator.resume(&mut &mut first),
+        Ok(GeneratorState::Complete(()))
+    );
+    assert_eq!(first, "");
+    assert_eq!(second, "12");
+}
+
+#[test]
+#[should_panic(expected = "can't suspend while the resume input is borrowed")]
+fn suspend_while_input_is_borrowed() {
+    let stack = EightMbStack::new().unwrap();
+    let mut generator = BorrowingGenerator::<Buffer, _, _, _>::new(stack, |yielder| {
+        yielder.with_input(|_buffer| yielder.suspend(()));
+    })
+    .unwrap();
+    let _ = generator.resume(&mut &mut String::new());
+}
+
+#[test]
+#[should_panic(expected = "the resume input is already borrowed")]
+fn borrow_input_twice() {
+    let stack = EightMbStack::new().unwrap();
+    let mut generator = BorrowingGenerator::<Buffer, (), _, _>::new(stack, |yielder| {
+        yielder.with_input(|_buffer| yielder.with_input(|_buffer| ()));
+    })
+    .unwrap();
+    let _ = generator.resume(&mut &mut String::new());
+}
@@ -0,0 +1,48 @@
+// The budget is process-wide, it has its own test binary so that it doesn't affect other tests.
+// All checks are part of a single test, because tests run in parallel.
+#![cfg(feature = "std")]
+
+use std::io::ErrorKind;
+
+use switcheroo::stack::*;
+
+#[test]
+fn stacks_respect_budget() {
+    assert_eq!(budget(), None);
+    assert_eq!(resident_bytes(), None);
+    let before = reserved_bytes();
+
+    set_budget(Some(before + 2 * 1024 * 1024 + 4096));
+    let first = OneMbStack::new().unwrap();
+    assert_eq!(reserved_bytes(), before + 1024 * 1024 + 4096);
+    let second = HeapStack::with_size(1024 * 1024).unwrap();
+
+    let error = StackBuilder::new().size(64 * 1024).build().err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+    assert!(error.to_string().contains("budget"));
+    assert!(StackArena::new(64 * 1024, 2).is_err());
+
+    // Dropping a stack frees its part of the budget.
+    drop(first);
+    set_track_residency(true);
+    let third = StackBuilder::new()
+        .size(64 * 1024)
+        .prefault_pages(16)
+        .build()
+        .unwrap();
+    assert!(resident_bytes().unwrap() >= 16 * 4096);
+
+    // Residency is tracked without a budget as well.
+    set_budget(None);
+    let fourth = StackBuilder::new()
+        .size(64 * 1024)
+        .prefault_pages(16)
+        .build()
+        .unwrap();
+    drop(second);
+    assert!(reserved_bytes() >= third.size() + fourth.size());
+    drop(third);
+    assert!(resident_bytes().unwrap() >= 16 * 4096);
+    set_track_residency(false);
+    assert_eq!(resident_bytes(), None);
+}
@@ -0,0 +1,52 @@
+#![cfg(feature = "std")]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use switcheroo::from_callback;
+use switcheroo::stack::*;
+
+// Pushes every line through the callback, like a visitor-style API.
+fn for_each_line(text: &str, callback: &mut dyn FnMut(&str)) {
+    for line in text.lines() {
+        callback(line);
+    }
+}
+
+#[test]
+fn iterate_over_callback() {
+    let text = "one\ntwo\nthree";
+    let stack = EightMbStack::new().unwrap();
+    let lines = from_callback(stack, |emit| {
+        for_each_line(text, &mut |line| emit(line.to_uppercase()));
+    })
+    .unwrap();
+    assert_eq!(lines.collect::<Vec<_>>(), ["ONE", "TWO", "THREE"]);
+}
+
+struct DropMarker(Arc<AtomicBool>);
+
+impl Drop for DropMarker {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn drop_iterator_early() {
+    let dropped = Arc::new(AtomicBool::new(false));
+    let marker = DropMarker(dropped.clone());
+    let stack = EightMbStack::new().unwrap();
+    let mut numbers = from_callback(stack, move |emit| {
+        let _marker = marker;
+        for i in 0.. {
+            emit(i);
+        }
+    })
+    .unwrap();
+    assert_eq!(numbers.next(), Some(0));
+    assert_eq!(numbers.next(), Some(1));
+    assert!(!dropped.load(Ordering::Relaxed));
+    drop(numbers);
+    assert!(dropped.load(Ordering::Relaxed));
+}
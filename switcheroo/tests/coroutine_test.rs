@@ -0,0 +1,37 @@
+#![cfg(all(feature = "std", feature = "nightly"))]
+#![feature(coroutine_trait)]
+
+use std::ops::{Coroutine, CoroutineState};
+use std::pin::Pin;
+
+use switcheroo::stack::*;
+use switcheroo::Generator;
+
+// Accepts any coroutine, like code written against std's coroutines would.
+fn drive<C: Coroutine<u32, Yield = u32, Return = String> + Unpin>(mut coroutine: C) -> Vec<u32> {
+    let mut yielded = Vec::new();
+    let mut input = 1;
+    loop {
+        match Pin::new(&mut coroutine).resume(input) {
+            CoroutineState::Yielded(value) => yielded.push(value),
+            CoroutineState::Complete(value) => {
+                assert_eq!(value, "done");
+                return yielded;
+            }
+        }
+        input += 1;
+    }
+}
+
+#[test]
+fn generator_is_a_coroutine() {
+    let stack = EightMbStack::new().unwrap();
+    let generator = Generator::new(stack, |yielder, mut input: u32| {
+        while input < 4 {
+            input = yielder.suspend(input * 10);
+        }
+        "done".to_string()
+    })
+    .unwrap();
+    assert_eq!(drive(generator), [10, 20, 30]);
+}
@@ -0,0 +1,113 @@
+#![cfg(feature = "std")]
+
+use switcheroo::stack::*;
+use switcheroo::{GeneratorState, LendingGenerator};
+
+#[test]
+fn lend_records_from_stack() {
+    let stack = EightMbStack::new().unwrap();
+    let mut parser = LendingGenerator::new(stack, |yielder, ()| {
+        let input = "a=1;bb=22;ccc=333";
+        let mut record = [0u8; 16];
+        let mut records = 0;
+        for field in input.split(';') {
+            record[..field.len()].copy_from_slice(field.as_bytes());
+            yielder.suspend(&mut record[..field.len()]);
+            records += 1;
+        }
+        records
+    })
+    .unwrap();
+
+    let mut fields = Vec::new();
+    while let GeneratorState::Yielded(record) = parser.resume(()).unwrap() {
+        fields.push(String::from_utf8(record.to_vec()).unwrap());
+    }
+    assert_eq!(fields, ["a=1", "bb=22", "ccc=333"]);
+    assert!(parser.finished());
+}
+
+#[test]
+#[cfg(not(feature = "thread"))]
+fn lent_value_lives_on_generator_stack() {
+    let stack = EightMbStack::new().unwrap();
+    let range = stack.top() as usize..stack.bottom() as usize;
+    let mut generator = LendingGenerator::new(stack, |yielder, ()| {
+        let mut buffer = [1u8; 1024];
+        yielder.suspend(&mut buffer);
+    })
+    .unwrap();
+    match generator.resume(()).unwrap() {
+        GeneratorState::Yielded(buffer) => assert!(range.contains(&(buffer.as_ptr() as usize))),
+        GeneratorState::Complete(()) => unreachable!(),
+    }
+}
+
+#[test]
+fn mutate_lent_value() {
+    let stack = EightMbStack::new().unwrap();
+    let mut counter = LendingGenerator::new(stack, |yielder, step: u32| {
+        let mut value = 0;
+        let mut step = step;
+        while step != 0 {
+            value += step;
+            step = yielder.suspend(&mut value);
+        }
+        value
+    })
+    .unwrap();
+
+    match counter.resume(1).unwrap() {
+        GeneratorState::Yielded(value) => {
+            assert_eq!(*value, 1);
+            *value = 10;
+        }
+        GeneratorState::Complete(_) => unreachable!(),
+    }
+    assert_eq!(counter.resume(5), Ok(GeneratorState::Yielded(&mut 15)));
+    assert_eq!(counter.resume(0), Ok(GeneratorState::Complete(15)));
+}
+
+#[test]
+#[cfg(not(feature = "thread"))]
+fn measure_lent_buffer() {
+    let stack = EightMbStack::new().unwrap();
+    let mut generator =
+        LendingGenerator::with_measurement(stack, Measurement::Paint, |yielder, ()| {
+            let mut buffer = [1u8; 256 * 1024];
+            yielder.suspend(&mut buffer[..]);
+        })
+        .unwrap();
+    assert!(generator.stack_high_water_mark().unwrap() < 64 * 1024);
+
+    match generator.resume(()).unwrap() {
+        GeneratorState::Yielded(buffer) => assert_eq!(buffer.len(), 256 * 1024),
+        GeneratorState::Complete(()) => unreachable!(),
+    }
+    assert!(generator.stack_high_water_mark().unwrap() >= 256 * 1024);
+    assert!(!generator.abandoned());
+}
+
+#[test]
+fn lend_shared_references() {
+    let stack = EightMbStack::new().unwrap();
+    let mut generator = LendingGenerator::new_shared(stack, |yielder, ()| {
+        let name = String::from("wormhole");
+        yielder.suspend(name.as_str());
+        yielder.suspend(&name[..4]);
+        name.len()
+    })
+    .unwrap();
+    match generator.resume(()).unwrap() {
+        GeneratorState::Yielded(name) => assert_eq!(name, "wormhole"),
+        GeneratorState::Complete(_) => unreachable!(),
+    }
+    match generator.resume(()).unwrap() {
+        GeneratorState::Yielded(name) => assert_eq!(name, "worm"),
+        GeneratorState::Complete(_) => unreachable!(),
+    }
+    match generator.resume(()).unwrap() {
+        GeneratorState::Complete(len) => assert_eq!(len, 8),
+        GeneratorState::Yielded(_) => unreachable!(),
+    }
+}
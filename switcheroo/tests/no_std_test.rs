@@ -0,0 +1,64 @@
+// Only uses the parts of the API that are also available without the `std` feature.
+
+use std::mem::MaybeUninit;
+
+use switcheroo::stack::*;
+use switcheroo::{Generator, GeneratorState};
+
+// A stack on memory owned by the caller, like a runtime without std would provide it.
+struct OwnedStack(Box<[MaybeUninit<u8>]>);
+
+impl OwnedStack {
+    fn new(size: usize) -> OwnedStack {
+        OwnedStack(vec![MaybeUninit::uninit(); size].into_boxed_slice())
+    }
+}
+
+impl Stack for OwnedStack {
+    fn bottom(&self) -> *mut usize {
+        let end = self.0.as_ptr() as usize + self.0.len();
+        (end / STACK_ALIGNMENT * STACK_ALIGNMENT) as *mut usize
+    }
+
+    fn top(&self) -> *mut usize {
+        self.0.as_ptr() as *mut usize
+    }
+}
+
+#[test]
+fn switch_user_provided_stack() {
+    let mut add_one = Generator::new(OwnedStack::new(64 * 1024), |yielder, mut input| loop {
+        if input == 0 {
+            break;
+        }
+        input = yielder.suspend(input + 1);
+    })
+    .unwrap();
+    assert_eq!(add_one.resume(2), Ok(GeneratorState::Yielded(3)));
+    assert_eq!(add_one.resume(127), Ok(GeneratorState::Yielded(128)));
+    assert_eq!(add_one.resume(0), Ok(GeneratorState::Complete(())));
+    assert!(add_one.finished());
+}
+
+#[test]
+fn reuse_stack_of_unfinished_generator() {
+    let mut memory = vec![MaybeUninit::uninit(); 64 * 1024];
+    let mut generator = Generator::new(BorrowedStack::new(&mut memory), |yielder, ()| {
+        yielder.suspend(1);
+        yielder.suspend(2);
+    })
+    .unwrap();
+    assert_eq!(generator.resume(()), Ok(GeneratorState::Yielded(1)));
+    let stack = generator.stack();
+    let mut generator = Generator::new(stack, |yielder, ()| yielder.suspend(3)).unwrap();
+    assert_eq!(generator.resume(()), Ok(GeneratorState::Yielded(3)));
+    assert_eq!(generator.resume(()), Ok(GeneratorState::Complete(())));
+}
+
+#[test]
+fn reject_tiny_user_provided_stack() {
+    let error = Generator::<(), (), (), _>::new(OwnedStack::new(64), |_, ()| {})
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("too small"));
+}
@@ -1,4 +1,6 @@
-use std::io::Error;
+#![cfg(feature = "std")]
+
+use switcheroo::Error;
 
 use switcheroo::stack::*;
 
@@ -9,7 +11,12 @@ fn create_8_mb_stack() -> Result<(), Error> {
 }
 
 #[test]
+#[cfg(target_pointer_width = "64")]
 fn create_300k_8_mb_stacks() {
+    if !guards_keep_single_mapping() {
+        eprintln!("skipped, each stack takes two mappings without MADV_GUARD_INSTALL");
+        return;
+    }
     // Uses around 4 Gb of commited memory
     let mut stacks = vec![];
     for _i in 0..300_000 {
@@ -18,3 +25,358 @@ fn create_300k_8_mb_stacks() {
         stacks.push(stack);
     }
 }
+
+// Before Linux 6.13 guard pages split each stack into two mappings, 300k stacks would run into the
+// `vm.max_map_count` limit.
+#[cfg(target_os = "linux")]
+fn guards_keep_single_mapping() -> bool {
+    const MADV_GUARD_INSTALL: libc::c_int = 102;
+    unsafe {
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        let ptr = libc::mmap(
+            std::ptr::null_mut(),
+            2 * page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANON,
+            -1,
+            0,
+        );
+        assert_ne!(ptr, libc::MAP_FAILED);
+        let supported = libc::madvise(ptr, page_size, MADV_GUARD_INSTALL) == 0;
+        libc::munmap(ptr, 2 * page_size);
+        supported
+    }
+}
+
+// Only Linux has a low limit on the number of mappings.
+#[cfg(not(target_os = "linux"))]
+fn guards_keep_single_mapping() -> bool {
+    true
+}
+
+#[test]
+fn stack_reports_size() -> Result<(), Error> {
+    let stack = EightMbStack::new()?;
+    assert_eq!(stack.size(), 8 * 1024 * 1024);
+    let range = stack.usable_range();
+    assert_eq!(range.start, stack.top() as usize);
+    assert_eq!(range.end, stack.bottom() as usize);
+    assert_eq!(stack.bottom() as usize % STACK_ALIGNMENT, 0);
+
+    let stack = ConfigurableStack::with_params(StackBuilder::new().size(64 * 1024))?;
+    assert_eq!(stack.size(), 64 * 1024);
+    Ok(())
+}
+
+#[test]
+fn create_configured_stacks() -> Result<(), Error> {
+    let stack = StackBuilder::new().size(100_000).build()?;
+    assert!(stack.bottom() as usize - stack.top() as usize >= 100_000);
+
+    StackBuilder::new()
+        .size(256 * 1024)
+        .prefault_pages(4)
+        .huge_pages(true)
+        .overcommit(false)
+        .build()?;
+    Ok(())
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn configure_guard_size() -> Result<(), Error> {
+    let stack = StackBuilder::new().guard_size(64 * 1024).build()?;
+    assert_eq!(stack.guard_size(), 64 * 1024);
+    let stack = StackBuilder::new().guard_size(0).build()?;
+    assert_eq!(stack.guard_size(), 0);
+    Ok(())
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn locked_stack() -> Result<(), Error> {
+    let stack = StackBuilder::new().size(64 * 1024).lock(true).build()?;
+    // All pages are already backed by memory and can be written to.
+    unsafe { stack.bottom().sub(1).write_volatile(1) };
+    Ok(())
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn guard_page_below_top() {
+    let stack = OneMbStack::new().unwrap();
+    assert!(stack.guard_size() > 0);
+    assert_eq!(stack.top() as usize % stack.guard_size(), 0);
+}
+
+// Faults are recovered from on Linux, on other Unix platforms a stack overflow aborts the process.
+#[test]
+#[cfg(all(
+    target_family = "unix",
+    not(target_os = "linux"),
+    not(feature = "thread")
+))]
+fn stack_overflow_is_reported() {
+    use std::process::Command;
+    use switcheroo::{Generator, GeneratorState};
+
+    // The overflow aborts the process, so it's triggered in a child process running just this test.
+    if std::env::var("SWITCHEROO_OVERFLOW_CHILD").is_ok() {
+        let stack = OneMbStack::new().unwrap();
+        let mut generator = Generator::new(stack, |_yielder, input: u64| {
+            rec(input);
+        })
+        .unwrap();
+        let _: Result<GeneratorState<(), ()>, _> = generator.resume(2_000);
+        unreachable!();
+    }
+
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["stack_overflow_is_reported", "--exact", "--nocapture"])
+        .env("SWITCHEROO_OVERFLOW_CHILD", "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("switcheroo stack overflow in generator"),
+        "{}",
+        stderr
+    );
+}
+
+// Uses 1 Kb per iteration
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+fn rec(n: u64) -> u8 {
+    let x: [u8; 1024] = [1; 1024];
+    if n < 1 {
+        x[0]
+    } else {
+        rec(n - 1) + std::hint::black_box(x)[n as usize % 1024]
+    }
+}
+
+#[test]
+fn pool_reuses_stacks() -> Result<(), Error> {
+    let pool: StackPool = StackPool::new();
+    let stack = pool.get()?;
+    let top = stack.top();
+    pool.put(stack);
+    assert_eq!(pool.stats().stacks_retained, 1);
+    assert_eq!(pool.stats().bytes_retained, 8 * 1024 * 1024);
+
+    let stack = pool.get()?;
+    assert_eq!(stack.top(), top);
+    let stats = pool.stats();
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.stacks_retained, 0);
+    assert_eq!(stats.bytes_retained, 0);
+    Ok(())
+}
+
+#[test]
+fn pool_zeroes_returned_stacks() -> Result<(), Error> {
+    let pool: StackPool<OneMbStack> = PoolBuilder::new().zero(true).build();
+    let stack = pool.get()?;
+    unsafe { *stack.bottom().sub(1) = 42 };
+    pool.put(stack);
+
+    let stack = pool.get()?;
+    assert_eq!(unsafe { *stack.bottom().sub(1) }, 0);
+    Ok(())
+}
+
+#[test]
+fn pool_respects_capacity() -> Result<(), Error> {
+    let pool: StackPool<OneMbStack> = PoolBuilder::new().capacity(2).build();
+    let stacks = vec![pool.get()?, pool.get()?, pool.get()?];
+    for stack in stacks {
+        pool.put(stack);
+    }
+    let stats = pool.stats();
+    assert_eq!(stats.misses, 3);
+    assert_eq!(stats.stacks_retained, 2);
+    assert_eq!(stats.discarded, 1);
+    Ok(())
+}
+
+#[test]
+fn pool_shares_stacks_between_threads() -> Result<(), Error> {
+    let pool: StackPool<OneMbStack> = PoolBuilder::new().thread_cache(1).build();
+    let thread_pool = pool.clone();
+    std::thread::spawn(move || {
+        // One stack stays in the thread cache until the thread exits, the other one is shared.
+        let stacks = vec![thread_pool.get().unwrap(), thread_pool.get().unwrap()];
+        for stack in stacks {
+            thread_pool.put(stack);
+        }
+    })
+    .join()
+    .unwrap();
+
+    pool.get()?;
+    pool.get()?;
+    let stats = pool.stats();
+    assert_eq!(stats.hits, 2);
+    assert_eq!(stats.misses, 2);
+    Ok(())
+}
+
+static DROPPED_COUNTED_STACKS: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+
+// Counts how many of its instances were dropped.
+struct CountedStack(OneMbStack);
+
+impl Stack for CountedStack {
+    fn bottom(&self) -> *mut usize {
+        self.0.bottom()
+    }
+
+    fn top(&self) -> *mut usize {
+        self.0.top()
+    }
+
+    fn guard_size(&self) -> usize {
+        self.0.guard_size()
+    }
+}
+
+impl NewStack for CountedStack {
+    type Params = ();
+
+    fn with_params(_: ()) -> Result<Self, Error> {
+        OneMbStack::new().map(CountedStack)
+    }
+}
+
+impl RecyclableStack for CountedStack {
+    fn reclaim(&self, zero: bool) -> Result<(), Error> {
+        self.0.reclaim(zero)
+    }
+}
+
+impl Drop for CountedStack {
+    fn drop(&mut self) {
+        DROPPED_COUNTED_STACKS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn dropped_pool_drops_stacks_cached_by_other_threads() {
+    let pool: StackPool<CountedStack> = PoolBuilder::new().thread_cache(2).build();
+    let thread_pool = pool.clone();
+    let (cached, cached_rx) = std::sync::mpsc::channel();
+    let (dropped, dropped_rx) = std::sync::mpsc::channel();
+    let thread = std::thread::spawn(move || {
+        let stacks = vec![thread_pool.get().unwrap(), thread_pool.get().unwrap()];
+        for stack in stacks {
+            thread_pool.put(stack);
+        }
+        drop(thread_pool);
+        cached.send(()).unwrap();
+        // Keep the thread and its cache alive until the pool is gone.
+        dropped_rx.recv().unwrap();
+    });
+
+    cached_rx.recv().unwrap();
+    assert_eq!(pool.stats().stacks_retained, 2);
+    drop(pool);
+    assert_eq!(
+        DROPPED_COUNTED_STACKS.load(std::sync::atomic::Ordering::SeqCst),
+        2
+    );
+    dropped.send(()).unwrap();
+    thread.join().unwrap();
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn create_stacks_from_arena() -> Result<(), Error> {
+    let arena = StackArena::new(64 * 1024, 3)?;
+    let stacks = vec![arena.stack()?, arena.stack()?, arena.stack()?];
+    assert!(arena.stack().is_err());
+    for pair in stacks.windows(2) {
+        assert_eq!(pair[0].size(), 64 * 1024);
+        assert_eq!(pair[0].guard_size(), 4096);
+        // Each stack is followed by the guard region of the next one.
+        assert_eq!(pair[1].top() as usize - pair[0].bottom() as usize, 4096);
+    }
+    drop(stacks);
+    assert_eq!(arena.available(), 3);
+    arena.reclaim()?;
+    Ok(())
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn empty_arena_is_rejected() {
+    let error = StackArena::new(64 * 1024, 0).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn arena_zeroes_returned_stacks() -> Result<(), Error> {
+    let arena = StackArena::new(64 * 1024, 1)?;
+    arena.set_zero(true);
+    let stack = arena.stack()?;
+    unsafe { *stack.bottom().sub(1) = 42 };
+    drop(stack);
+
+    let stack = arena.stack()?;
+    assert_eq!(unsafe { *stack.bottom().sub(1) }, 0);
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_family = "unix", target_pointer_width = "64"))]
+fn create_300k_8_mb_stacks_from_arena() -> Result<(), Error> {
+    // Without guard regions the whole arena is a single mapping, no matter how many stacks it has.
+    let arena = StackArena::with_guard_size(8 * 1024 * 1024, 0, 300_000)?;
+    let mut stacks = vec![];
+    for _i in 0..300_000 {
+        stacks.push(arena.stack()?);
+    }
+    Ok(())
+}
+
+#[test]
+fn raw_stack_is_aligned() {
+    let mut memory = vec![0u8; 64 * 1024 + 64];
+    for offset in 0..32 {
+        let stack = unsafe { RawStack::from_raw_parts(memory.as_mut_ptr().add(offset), 64 * 1024) };
+        assert_eq!(stack.bottom() as usize % STACK_ALIGNMENT, 0);
+        assert_eq!(stack.top() as usize % std::mem::size_of::<usize>(), 0);
+        assert!(stack.top() as usize >= memory.as_ptr() as usize + offset);
+        assert!(stack.bottom() as usize <= memory.as_ptr() as usize + offset + 64 * 1024);
+        assert!(stack.size() > 64 * 1024 - 32);
+    }
+}
+
+#[test]
+fn create_heap_stacks() -> Result<(), Error> {
+    let stack = HeapStack::new()?;
+    assert_eq!(stack.size(), 1024 * 1024);
+    let stack = HeapStack::with_size(100_001)?;
+    assert!(stack.size() >= 100_001);
+    assert_eq!(stack.bottom() as usize % STACK_ALIGNMENT, 0);
+    assert_eq!(stack.guard_size(), 0);
+    let stack = HeapStack::with_size(1)?;
+    assert_eq!(stack.size(), MIN_STACK_SIZE);
+    Ok(())
+}
+
+#[test]
+fn errors_convert_to_io_errors() {
+    // Larger than any allocation can be.
+    let error = HeapStack::with_size(isize::MAX as usize).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(error.raw_os_error(), None);
+
+    let io_error = std::io::Error::from(error.clone());
+    assert_eq!(io_error.kind(), error.kind());
+    assert_eq!(io_error.to_string(), error.message());
+    assert_eq!(Error::from(io_error), error);
+}
@@ -1,5 +1,7 @@
+#![cfg(feature = "std")]
+
 use switcheroo::stack::*;
-use switcheroo::Generator;
+use switcheroo::{Generator, GeneratorState};
 
 #[test]
 fn switch_stack() {
@@ -12,13 +14,73 @@ fn switch_stack() {
             }
             input = yielder.suspend(input + 1);
         }
-    });
-    assert_eq!(add_one.resume(2), Some(3));
-    assert_eq!(add_one.resume(127), Some(128));
-    assert_eq!(add_one.resume(-1), Some(0));
-    assert_eq!(add_one.resume(0), None);
-    assert_eq!(add_one.resume(0), None);
-    assert_eq!(add_one.resume(0), None);
+    })
+    .unwrap();
+    assert_eq!(add_one.resume(2), Ok(GeneratorState::Yielded(3)));
+    assert_eq!(add_one.resume(127), Ok(GeneratorState::Yielded(128)));
+    assert_eq!(add_one.resume(-1), Ok(GeneratorState::Yielded(0)));
+    assert_eq!(add_one.resume(0), Ok(GeneratorState::Complete(())));
+    assert!(add_one.finished());
+}
+
+#[test]
+fn return_value_completes_generator() {
+    let stack = EightMbStack::new().unwrap();
+    let mut sum = Generator::new(stack, |yielder, mut input: u32| {
+        let mut sum = 0;
+        while input != 0 {
+            sum += input;
+            input = yielder.suspend(sum);
+        }
+        format!("sum {}", sum)
+    })
+    .unwrap();
+    assert_eq!(sum.resume(2), Ok(GeneratorState::Yielded(2)));
+    assert_eq!(sum.resume(3), Ok(GeneratorState::Yielded(5)));
+    assert_eq!(
+        sum.resume(0),
+        Ok(GeneratorState::Complete("sum 5".to_string()))
+    );
+    assert!(sum.finished());
+}
+
+#[test]
+fn iterate_over_generator() {
+    let stack = EightMbStack::new().unwrap();
+    let countdown = Generator::new(stack, |yielder, ()| {
+        for i in (1..=3).rev() {
+            yielder.suspend(i);
+        }
+        "done"
+    })
+    .unwrap();
+    let mut values = Vec::new();
+    for value in countdown {
+        values.push(value);
+    }
+    assert_eq!(values, [3, 2, 1]);
+
+    let stack = EightMbStack::new().unwrap();
+    let mut numbers = Generator::new(stack, |yielder, ()| {
+        let _local_variable = Box::new(0);
+        let mut i = 0;
+        loop {
+            i += 1;
+            yielder.suspend(i);
+        }
+    })
+    .unwrap();
+    assert_eq!(numbers.by_ref().take(2).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(numbers.next(), Some(3));
+}
+
+#[test]
+#[should_panic(expected = "generator resumed after completion")]
+fn resume_after_completion_panics() {
+    let stack = EightMbStack::new().unwrap();
+    let mut generator = Generator::<(), (), _, _>::new(stack, |_yielder, ()| 1).unwrap();
+    assert_eq!(generator.resume(()), Ok(GeneratorState::Complete(1)));
+    let _ = generator.resume(());
 }
 
 #[test]
@@ -27,9 +89,10 @@ fn extend_small_stack() {
     let mut blow_stack = Generator::new(stack, |yielder, input| {
         rec(input);
         yielder.suspend(Some(0));
-    });
+    })
+    .unwrap();
     // This will use 7 Mb of stack, more than the first 4 Kb commited memory on Windows
-    blow_stack.resume(7_000);
+    blow_stack.resume(7_000).unwrap();
 }
 
 // Uses 1 Kb per iteration
@@ -42,14 +105,41 @@ fn rec(n: u64) -> u8 {
     }
 }
 
+// Like `rec`, but the compiler can't turn it into a loop.
+#[cfg(all(target_os = "linux", not(feature = "thread")))]
+fn rec_no_tail(n: u64) -> u8 {
+    let x: [u8; 1024] = [1; 1024];
+    if n < 1 {
+        x[0]
+    } else {
+        rec_no_tail(n - 1) + std::hint::black_box(x)[n as usize % 1024]
+    }
+}
+
+#[test]
+fn switch_configured_stack() {
+    let stack = StackBuilder::new()
+        .size(64 * 1024)
+        .prefault_pages(1)
+        .build()
+        .unwrap();
+    let mut add_one = Generator::new(stack, |yielder, input| {
+        yielder.suspend(input + 1);
+    })
+    .unwrap();
+    assert_eq!(add_one.resume(1), Ok(GeneratorState::Yielded(2)));
+    assert_eq!(add_one.resume(0), Ok(GeneratorState::Complete(())));
+}
+
 #[test]
 #[should_panic]
 fn panic_on_different_stack() {
     let stack = EightMbStack::new().unwrap();
     let mut add_one = Generator::new(stack, |_yielder, mut _input| {
         panic!("Ups");
-    });
-    let _: u32 = add_one.resume(0).unwrap();
+    })
+    .unwrap();
+    let _: Result<GeneratorState<u32, ()>, _> = add_one.resume(0);
 }
 
 #[test]
@@ -60,6 +150,443 @@ fn drop_stack_with_unwind() {
         yielder.suspend(());
         yielder.suspend(());
         yielder.suspend(());
-    });
-    let _: () = add_one.resume(()).unwrap();
+    })
+    .unwrap();
+    assert_eq!(add_one.resume(()), Ok(GeneratorState::Yielded(())));
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "thread")))]
+fn stack_overflow_returns_fault() {
+    let stack = OneMbStack::new().unwrap();
+    let mut overflow = Generator::new(stack, |yielder, input| {
+        rec_no_tail(input);
+        yielder.suspend(());
+    })
+    .unwrap();
+    let fault = overflow.resume(2_000).unwrap_err();
+    assert!(fault.is_stack_overflow());
+    assert!(overflow.abandoned());
+    assert!(overflow.finished());
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "thread")))]
+fn arena_stack_overflow_returns_fault() {
+    let arena = StackArena::new(256 * 1024, 2).unwrap();
+    let _neighbour = arena.stack().unwrap();
+    let stack = arena.stack().unwrap();
+    let mut overflow = Generator::new(stack, |yielder, input| {
+        rec_no_tail(input);
+        yielder.suspend(());
+    })
+    .unwrap();
+    let fault = overflow.resume(2_000).unwrap_err();
+    assert!(fault.is_stack_overflow());
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "thread")))]
+fn segfault_returns_fault() {
+    let stack = EightMbStack::new().unwrap();
+    let mut segfault = Generator::new(stack, |yielder, address: usize| {
+        let _local_variable = Box::new(0);
+        unsafe { (address as *mut u8).write_volatile(1) };
+        yielder.suspend(());
+    })
+    .unwrap();
+    let fault = segfault.resume(0x10).unwrap_err();
+    assert!(!fault.is_stack_overflow());
+    assert_eq!(fault.address(), 0x10);
+    assert!(segfault.abandoned());
+}
+
+#[test]
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "x86"),
+    not(feature = "thread")
+))]
+fn illegal_instruction_returns_fault() {
+    let stack = EightMbStack::new().unwrap();
+    let mut illegal = Generator::new(stack, |yielder, ()| {
+        unsafe { std::arch::asm!("ud2") };
+        yielder.suspend(());
+    })
+    .unwrap();
+    assert!(illegal.resume(()).is_err());
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "thread")))]
+fn fault_in_nested_generator() {
+    let stack = EightMbStack::new().unwrap();
+    let mut outer = Generator::new(stack, |yielder, ()| {
+        let stack = EightMbStack::new().unwrap();
+        let mut inner = Generator::new(stack, |yielder, address: usize| {
+            unsafe { (address as *mut u8).write_volatile(1) };
+            yielder.suspend(());
+        })
+        .unwrap();
+        let fault = inner.resume(0x10).unwrap_err();
+        yielder.suspend(fault.address());
+    })
+    .unwrap();
+    assert_eq!(outer.resume(()), Ok(GeneratorState::Yielded(0x10)));
+    assert_eq!(outer.resume(()), Ok(GeneratorState::Complete(())));
+}
+
+// Reports a bottom that is 8 bytes lower than the real one.
+struct MisalignedStack(EightMbStack);
+
+impl Stack for MisalignedStack {
+    fn bottom(&self) -> *mut usize {
+        unsafe { self.0.bottom().sub(1) }
+    }
+    fn top(&self) -> *mut usize {
+        self.0.top()
+    }
+}
+
+// Reports only the bottom 64 bytes as usable.
+struct TinyStack(EightMbStack);
+
+impl Stack for TinyStack {
+    fn bottom(&self) -> *mut usize {
+        self.0.bottom()
+    }
+    fn top(&self) -> *mut usize {
+        unsafe { self.0.bottom().sub(64 / std::mem::size_of::<usize>()) }
+    }
+}
+
+#[test]
+fn reject_invalid_stacks() {
+    let stack = MisalignedStack(EightMbStack::new().unwrap());
+    assert!(Generator::<(), (), (), _>::new(stack, |_yielder, ()| {}).is_err());
+    let stack = TinyStack(EightMbStack::new().unwrap());
+    assert!(Generator::<(), (), (), _>::new(stack, |_yielder, ()| {}).is_err());
+}
+
+#[test]
+fn drop_never_resumed_generator() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let dropped = Arc::new(AtomicBool::new(false));
+    struct DropMarker(Arc<AtomicBool>);
+    impl Drop for DropMarker {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+    let marker = DropMarker(dropped.clone());
+    let stack = EightMbStack::new().unwrap();
+    let generator = Generator::<(), (), (), _>::new(stack, move |_yielder, ()| {
+        let _marker = marker;
+        unreachable!();
+    })
+    .unwrap();
+    assert!(!generator.started());
+    assert!(!dropped.load(Ordering::Relaxed));
+    drop(generator);
+    assert!(dropped.load(Ordering::Relaxed));
+}
+
+#[test]
+fn reject_closure_larger_than_stack() {
+    let stack = HeapStack::with_size(16 * 1024).unwrap();
+    let buffer = [1u8; 16 * 1024];
+    let error = Generator::<(), (), (), _>::new(stack, move |_yielder, ()| {
+        std::hint::black_box(&buffer);
+    })
+    .err()
+    .unwrap();
+    assert!(error.to_string().contains("doesn't fit"));
+}
+
+#[inline(never)]
+#[cfg(not(feature = "thread"))]
+fn use_256_kb() {
+    let buffer = [1u8; 256 * 1024];
+    std::hint::black_box(&buffer);
+}
+
+#[test]
+#[cfg(not(feature = "thread"))]
+fn measure_stack_high_water_mark() {
+    for &measurement in &[Measurement::Paint, Measurement::Residency] {
+        let stack = EightMbStack::new().unwrap();
+        let mut generator = Generator::with_measurement(stack, measurement, |yielder, ()| {
+            use_256_kb();
+            yielder.suspend(());
+        })
+        .unwrap();
+        let before = generator.stack_high_water_mark().unwrap();
+        assert!(before < 64 * 1024, "{:?}: {}", measurement, before);
+
+        generator.resume(()).unwrap();
+        let after = generator.stack_high_water_mark().unwrap();
+        assert!(after >= 256 * 1024, "{:?}: {}", measurement, after);
+        assert!(after < 512 * 1024, "{:?}: {}", measurement, after);
+        assert!(generator.stack_bytes_resident().unwrap() >= 256 * 1024);
+    }
+}
+
+#[test]
+fn high_water_mark_requires_measurement() {
+    let stack = EightMbStack::new().unwrap();
+    let generator = Generator::new(stack, |yielder, ()| yielder.suspend(())).unwrap();
+    assert_eq!(generator.stack_high_water_mark(), None);
+    assert!(generator.stack_bytes_resident().unwrap() < 64 * 1024);
+}
+
+#[inline(never)]
+#[cfg(not(feature = "thread"))]
+fn remaining_below_buffer() -> usize {
+    let buffer = [1u8; 64 * 1024];
+    std::hint::black_box(&buffer);
+    switcheroo::remaining_stack().unwrap()
+}
+
+#[test]
+#[cfg(not(feature = "thread"))]
+fn query_remaining_stack() {
+    assert_eq!(switcheroo::remaining_stack(), None);
+    let stack = OneMbStack::new().unwrap();
+    let mut generator = Generator::new(stack, |yielder, ()| {
+        let before = yielder.stack_remaining().unwrap();
+        yielder.suspend((before, remaining_below_buffer()));
+    })
+    .unwrap();
+    let (before, after) = match generator.resume(()) {
+        Ok(GeneratorState::Yielded(remaining)) => remaining,
+        _ => unreachable!(),
+    };
+    assert!(before < 1024 * 1024 + 4096);
+    assert!(before - after >= 60 * 1024);
+    assert_eq!(switcheroo::remaining_stack(), None);
+}
+
+#[cfg(not(feature = "thread"))]
+fn grow_rec(n: u64) -> u64 {
+    switcheroo::maybe_grow(64 * 1024, 256 * 1024, || {
+        let buffer = [n as u8; 1024];
+        std::hint::black_box(&buffer);
+        if n == 0 {
+            0
+        } else {
+            1 + grow_rec(n - 1)
+        }
+    })
+}
+
+#[test]
+#[cfg(not(feature = "thread"))]
+fn maybe_grow_extends_stack() {
+    let stack = OneMbStack::new().unwrap();
+    let mut generator = Generator::new(stack, |yielder, n| {
+        yielder.suspend(grow_rec(n));
+    })
+    .unwrap();
+    // Needs more than 10 Mb of stack.
+    assert_eq!(
+        generator.resume(10_000),
+        Ok(GeneratorState::Yielded(10_000))
+    );
+}
+
+#[test]
+#[should_panic(expected = "panic on grown stack")]
+fn maybe_grow_propagates_panics() {
+    let stack = OneMbStack::new().unwrap();
+    let mut generator = Generator::new(stack, |yielder, ()| {
+        switcheroo::maybe_grow(usize::MAX, 64 * 1024, || panic!("panic on grown stack"));
+        yielder.suspend(());
+    })
+    .unwrap();
+    let _ = generator.resume(());
+}
+
+#[test]
+#[cfg(not(feature = "thread"))]
+fn stack_limit_follows_switches() {
+    let limit_ptr = switcheroo::stack_limit_ptr();
+    assert_eq!(switcheroo::stack_limit(), None);
+    assert_eq!(unsafe { *limit_ptr }, 0);
+
+    let outer_stack = OneMbStack::new().unwrap();
+    let outer_top = outer_stack.top() as usize;
+    let mut outer = Generator::new(outer_stack, |yielder, ()| {
+        let before = yielder.stack_limit().unwrap();
+
+        let inner_stack = OneMbStack::new().unwrap();
+        let inner_top = inner_stack.top() as usize;
+        let mut inner = Generator::new(inner_stack, |yielder, ()| {
+            yielder.suspend(unsafe { *switcheroo::stack_limit_ptr() });
+        })
+        .unwrap();
+        assert_eq!(inner.resume(()), Ok(GeneratorState::Yielded(inner_top)));
+
+        yielder.suspend((before, switcheroo::stack_limit().unwrap()));
+    })
+    .unwrap();
+    outer.set_stack_limit_slack(16 * 1024);
+
+    let (before, after) = match outer.resume(()) {
+        Ok(GeneratorState::Yielded(limits)) => limits,
+        _ => unreachable!(),
+    };
+    assert_eq!(before, outer_top + 16 * 1024);
+    assert_eq!(after, before);
+    assert_eq!(unsafe { *limit_ptr }, 0);
+}
+
+#[test]
+fn switch_borrowed_stack() {
+    let mut memory = vec![std::mem::MaybeUninit::uninit(); 256 * 1024];
+    let stack = BorrowedStack::new(&mut memory);
+    let mut add_one = Generator::new(stack, |yielder, mut input| loop {
+        if input == 0 {
+            break;
+        }
+        input = yielder.suspend(input + 1);
+    })
+    .unwrap();
+    assert_eq!(add_one.resume(2), Ok(GeneratorState::Yielded(3)));
+    assert_eq!(add_one.resume(0), Ok(GeneratorState::Complete(())));
+}
+
+#[test]
+fn switch_raw_stack() {
+    let mut memory = vec![0u8; 256 * 1024 + 3].into_boxed_slice();
+    // Starts at an unaligned address.
+    let stack = unsafe { RawStack::from_raw_parts(memory.as_mut_ptr().add(3), 256 * 1024) };
+    let mut generator = Generator::new(stack, |yielder, input: String| {
+        yielder.suspend(input.len());
+    })
+    .unwrap();
+    assert_eq!(
+        generator.resume("four".to_string()),
+        Ok(GeneratorState::Yielded(4))
+    );
+    drop(generator);
+    drop(memory);
+}
+
+#[test]
+fn reject_tiny_borrowed_stack() {
+    let mut memory = [std::mem::MaybeUninit::uninit(); 64];
+    let stack = BorrowedStack::new(&mut memory);
+    assert!(Generator::<(), (), (), _>::new(stack, |_, ()| {}).is_err());
+}
+
+#[test]
+fn switch_heap_stack() {
+    let stack = HeapStack::with_size(128 * 1024).unwrap();
+    let mut generator = Generator::new(stack, |yielder, input: Vec<u32>| {
+        let sum = input.iter().sum::<u32>();
+        let input = yielder.suspend(sum);
+        yielder.suspend(input.len() as u32);
+    })
+    .unwrap();
+    assert_eq!(
+        generator.resume(vec![1, 2, 3]),
+        Ok(GeneratorState::Yielded(6))
+    );
+    assert_eq!(generator.resume(vec![1, 2]), Ok(GeneratorState::Yielded(2)));
+    assert_eq!(generator.resume(vec![]), Ok(GeneratorState::Complete(())));
+}
+
+#[cfg(all(feature = "fp-state", target_arch = "x86_64"))]
+mod rounding {
+    use std::arch::asm;
+
+    const MASK: u32 = 0x6000;
+    pub const NEAREST: u32 = 0x0000;
+    pub const DOWN: u32 = 0x2000;
+    pub const UP: u32 = 0x4000;
+
+    fn mxcsr() -> u32 {
+        let mut mxcsr = 0u32;
+        unsafe { asm!("stmxcsr [{}]", in(reg) &mut mxcsr) };
+        mxcsr
+    }
+
+    pub fn get() -> u32 {
+        mxcsr() & MASK
+    }
+
+    pub fn set(mode: u32) {
+        let mxcsr = (mxcsr() & !MASK) | mode;
+        unsafe { asm!("ldmxcsr [{}]", in(reg) &mxcsr) };
+    }
+}
+
+#[cfg(all(feature = "fp-state", target_arch = "aarch64"))]
+mod rounding {
+    use std::arch::asm;
+
+    const MASK: usize = 0xc0_0000;
+    pub const NEAREST: usize = 0x00_0000;
+    pub const UP: usize = 0x40_0000;
+    pub const DOWN: usize = 0x80_0000;
+
+    fn fpcr() -> usize {
+        let fpcr: usize;
+        unsafe { asm!("mrs {}, fpcr", out(reg) fpcr) };
+        fpcr
+    }
+
+    pub fn get() -> usize {
+        fpcr() & MASK
+    }
+
+    pub fn set(mode: usize) {
+        let fpcr = (fpcr() & !MASK) | mode;
+        unsafe { asm!("msr fpcr, {}", in(reg) fpcr) };
+    }
+}
+
+#[test]
+#[cfg(all(
+    feature = "fp-state",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn rounding_mode_is_kept_per_context() {
+    let stack = EightMbStack::new().unwrap();
+    let mut generator = Generator::new(stack, |yielder, ()| {
+        rounding::set(rounding::UP);
+        yielder.suspend(rounding::get());
+        yielder.suspend(rounding::get());
+    })
+    .unwrap();
+
+    assert_eq!(rounding::get(), rounding::NEAREST);
+    assert_eq!(
+        generator.resume(()),
+        Ok(GeneratorState::Yielded(rounding::UP))
+    );
+    assert_eq!(rounding::get(), rounding::NEAREST);
+    rounding::set(rounding::DOWN);
+    assert_eq!(
+        generator.resume(()),
+        Ok(GeneratorState::Yielded(rounding::UP))
+    );
+    assert_eq!(rounding::get(), rounding::DOWN);
+    rounding::set(rounding::NEAREST);
+}
+
+#[test]
+#[cfg(feature = "thread")]
+fn stack_is_unknown_with_thread_backend() {
+    let stack = OneMbStack::new().unwrap();
+    let mut generator = Generator::new(stack, |yielder, ()| {
+        yielder.suspend((yielder.stack_remaining(), yielder.stack_limit()));
+    })
+    .unwrap();
+    assert_eq!(
+        generator.resume(()),
+        Ok(GeneratorState::Yielded((None, None)))
+    );
 }
@@ -1,8 +1,15 @@
+#![allow(clippy::bool_assert_comparison)]
+
 use async_executor::LocalExecutor;
 use async_wormhole::AsyncWormhole;
 use backtrace::Backtrace;
 use switcheroo::stack::*;
 
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
 #[test]
 fn async_yield() {
     let stack = EightMbStack::new().unwrap();
@@ -16,7 +23,7 @@ fn async_yield() {
     })
     .unwrap();
     let output = futures::executor::block_on(task);
-    assert_eq!(output, 42);
+    assert_eq!(output.unwrap(), 42);
 }
 
 #[test]
@@ -31,7 +38,7 @@ fn async_yield_panics() {
         panic!();
     })
     .unwrap();
-    futures::executor::block_on(task);
+    futures::executor::block_on(task).unwrap();
 }
 
 #[test]
@@ -69,5 +76,91 @@ fn backtrace_test() {
     })
     .unwrap();
 
-    futures::executor::block_on(task);
+    futures::executor::block_on(task).unwrap();
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "thread")))]
+fn async_fault_resolves_to_error() {
+    let stack = EightMbStack::new().unwrap();
+    let task = AsyncWormhole::<_, _, fn()>::new(stack, |mut yielder| {
+        let address = yielder.async_suspend(async { 0x10 });
+        unsafe { (address as *mut u8).write_volatile(1) };
+    })
+    .unwrap();
+    let fault = futures::executor::block_on(task).unwrap_err();
+    assert_eq!(fault.address(), 0x10);
+}
+
+#[inline(never)]
+#[cfg(not(feature = "thread"))]
+fn use_128_kb() {
+    let buffer = [1u8; 128 * 1024];
+    std::hint::black_box(&buffer);
+}
+
+#[test]
+#[cfg(not(feature = "thread"))]
+fn async_stack_high_water_mark() {
+    let stack = EightMbStack::new().unwrap();
+    let mut task =
+        AsyncWormhole::<_, _, fn()>::with_measurement(stack, Measurement::Paint, |mut yielder| {
+            use_128_kb();
+            yielder.async_suspend(async { 42 })
+        })
+        .unwrap();
+    let output = futures::executor::block_on(&mut task);
+    assert_eq!(output.unwrap(), 42);
+    assert!(task.stack_high_water_mark().unwrap() >= 128 * 1024);
+}
+
+static WAKER_CLONES: AtomicUsize = AtomicUsize::new(0);
+
+static COUNTING_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |data| {
+        WAKER_CLONES.fetch_add(1, Ordering::SeqCst);
+        RawWaker::new(data, &COUNTING_VTABLE)
+    },
+    |_| {},
+    |_| {},
+    |_| {},
+);
+
+// Returns `Pending` a number of times without touching the waker.
+struct PendingFor(usize);
+
+impl Future for PendingFor {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 == 0 {
+            Poll::Ready(())
+        } else {
+            self.0 -= 1;
+            Poll::Pending
+        }
+    }
+}
+
+#[test]
+fn async_poll_does_not_clone_waker() {
+    let waker = unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &COUNTING_VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    let stack = EightMbStack::new().unwrap();
+    let mut task = AsyncWormhole::<_, _, fn()>::new(stack, |mut yielder| {
+        yielder.async_suspend(PendingFor(3));
+        yielder.async_suspend(PendingFor(2));
+        42
+    })
+    .unwrap();
+    let mut polls = 0;
+    let output = loop {
+        polls += 1;
+        if let Poll::Ready(output) = Pin::new(&mut task).poll(&mut cx) {
+            break output;
+        }
+    };
+    assert_eq!(output.unwrap(), 42);
+    assert_eq!(polls, 6);
+    assert_eq!(WAKER_CLONES.load(Ordering::SeqCst), 0);
 }